@@ -0,0 +1,436 @@
+use std::{fs, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+
+use crate::frame_processing::ThresholdMode;
+use crate::particle_system::{BoundaryMode, EffectType, ParticleShape};
+
+// Settings of a run, loaded from a TOML file
+// Missing keys keep their default value, unknown keys are rejected to catch typos
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // Maximum possible pixel size
+    pub pixel_size: i32,
+    // Spacing between pixels
+    pub pixel_spacing: i32,
+    // Name of the window
+    pub window_name: String,
+    // Width of the window
+    pub window_width: i32,
+    // Height of the window
+    pub window_height: i32,
+    // Width of the video resolution
+    pub video_resolution_width: i32,
+    // Height of the video resolution
+    pub video_resolution_height: i32,
+    // Distance to detect interference
+    pub objects_interference_distance: i32,
+    // Contrast of the webcams
+    pub webcam_contrast: f64,
+    // Brightness of the webcams
+    pub webcam_brightness: f64,
+    // Flip axis of the webcams (unset to disable)
+    pub webcam_flip: Option<i32>,
+    // Native capture resolution and frame rate requested from the webcams
+    pub webcam_capture_width: i32,
+    pub webcam_capture_height: i32,
+    pub webcam_capture_fps: f64,
+    // Region of the main sources to keep as [x, y, width, height], unset for the full frame
+    pub roi: Option<[i32; 4]>,
+    // Attempts to open a webcam that is not ready yet
+    pub webcam_open_attempts: u32,
+    // Wait between the attempts to open a webcam, in milliseconds
+    pub webcam_open_retry_delay_ms: u64,
+    // Time after which a hanging webcam or stream read is given up and the source reopened,
+    // in milliseconds (unset to wait forever)
+    pub capture_read_timeout_ms: Option<u64>,
+    // Extensions of the clips in the folder
+    pub video_extensions: Vec<String>,
+    // Frames over which the clips dissolve into the next one (0 for a cut)
+    pub clip_crossfade_frames: u32,
+    // Contrast of the video files
+    pub file_contrast: f64,
+    // Brightness of the video files
+    pub file_brightness: f64,
+    // How likely each effect is picked on interference
+    pub effect_weights: Vec<(EffectType, f64)>,
+    // Rotation direction of the swirl effect
+    pub swirl_clockwise: bool,
+    // How fast particles gather back to their origin
+    pub reassembly_speed: f64,
+    // Align the particle origins to the pixel grid
+    pub snap_to_grid: bool,
+    // Particle behavior at the window edges
+    pub boundary_mode: BoundaryMode,
+    // Share of the previous frame kept (1.0 for no trails)
+    pub trail_decay: f64,
+    // Relative variation of the particle sizes
+    pub size_jitter: f64,
+    // Frames new particles fade in over (0 to disable)
+    pub spawn_frames: u32,
+    // Frames particles that left their object fade out over (0 to drop them at once)
+    pub leave_frames: u32,
+    // Updates after which an effect is cut short and the particles snap back (unlimited if unset)
+    pub effect_max_frames: Option<u32>,
+    // Push apart overlapping particles during the effects, at a performance cost
+    pub particle_collision: bool,
+    // Shape of the particles
+    pub particle_shape: ParticleShape,
+    // Smooth the particle edges, at a small drawing cost
+    pub antialiasing: bool,
+    // Background color (BGR)
+    pub background_color: [f64; 3],
+    // Background image, replacing the color
+    pub background_image: Option<String>,
+    // Color the particles fade towards (BGR), the background color if unset
+    pub fade_color: Option<[f64; 3]>,
+    // Threshold to separate objects from the background
+    pub grayscale_threshold: i32,
+    // Folder of the snapshots
+    pub snapshot_folder: String,
+    // Folder of the particle state dumps
+    pub state_folder: String,
+    // Frame rate of the recorded video
+    pub recording_fps: f64,
+    // Thresholding of the grayscale frames
+    pub threshold_mode: ThresholdMode,
+    // Isolate the moving objects with background subtraction, replacing the threshold
+    pub background_subtraction: bool,
+    // HSV range of the objects, replacing the threshold
+    pub color_key_range: Option<([f64; 3], [f64; 3])>,
+    // Kernel size to remove mask noise (0 to disable)
+    pub denoise_kernel_size: i32,
+    // Track every object instead of only the largest
+    pub track_all_contours: bool,
+    // Minimum area of the tracked objects
+    pub min_contour_area: f64,
+    // Minimum area (px²) of any object, smaller blobs don't spawn particles (0 to disable)
+    pub min_object_area: f64,
+    // How far (in pixels) the simplified object contours may deviate (0 to disable)
+    pub contour_epsilon: f64,
+    // Share of object pixels a cell needs to become a particle
+    pub min_cell_coverage: f64,
+    // Scale of the object masks relative to the video
+    pub processing_scale: f64,
+    // Share of the previous closest points kept each loop, to smooth them (0 to disable)
+    pub closest_points_smoothing: f64,
+    // Explosion intensity of a gentle touch
+    pub min_explosion_intensity: f64,
+    // Start effects at the center of the clip object
+    pub effect_at_centroid: bool,
+    // Play the interference effect on the live layers too
+    pub live_layers_react: bool,
+    // Color fast explosion particles glow in (BGR)
+    pub explosion_hot_color: [f64; 3],
+    // Downward acceleration of the break effect
+    pub break_gravity: f64,
+    // Distance of the break floor above the bottom edge
+    pub break_floor_offset: i32,
+    // How much particles bounce off the break floor
+    pub break_floor_restitution: f64,
+    // Darker particles fall faster and lighter ones flutter in the break effect
+    pub break_mass_variation: bool,
+    // Force blowing the break and explosion debris per update, as [x, y]
+    pub wind: [f64; 2],
+    // Distance per pair of objects
+    pub pair_interference_distances: Vec<(usize, usize, i32)>,
+    // Draw the closest points of the objects and the line between them
+    pub draw_interference: bool,
+    // Draw the interference line as an electric arc
+    pub interference_arc: bool,
+    // Arc offset relative to its length
+    pub interference_arc_jaggedness: f64,
+    // How many times the arc is subdivided
+    pub interference_arc_subdivisions: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pixel_size: 10,
+            pixel_spacing: 0,
+            window_name: String::from("Window"),
+            window_width: 960,
+            window_height: 540,
+            video_resolution_width: 1920,
+            video_resolution_height: 1080,
+            objects_interference_distance: 10,
+            webcam_contrast: 1.0,
+            webcam_brightness: 90.0,
+            webcam_flip: Some(1),
+            webcam_capture_width: 1920,
+            webcam_capture_height: 1080,
+            webcam_capture_fps: 30.0,
+            roi: None,
+            webcam_open_attempts: 5,
+            webcam_open_retry_delay_ms: 1000,
+            capture_read_timeout_ms: Some(2000),
+            video_extensions: ["mp4", "mov", "mkv", "avi"]
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+            clip_crossfade_frames: 0,
+            file_contrast: 1.0,
+            file_brightness: 0.0,
+            effect_weights: vec![
+                (EffectType::Explosion, 1.0),
+                (EffectType::Break, 1.0),
+                (EffectType::GravityWell, 1.0),
+                (EffectType::Implosion, 1.0),
+                (EffectType::Fireworks, 1.0),
+            ],
+            swirl_clockwise: true,
+            reassembly_speed: 0.05,
+            snap_to_grid: false,
+            boundary_mode: BoundaryMode::Clamp,
+            trail_decay: 1.0,
+            size_jitter: 0.0,
+            spawn_frames: 0,
+            leave_frames: 6,
+            particle_collision: false,
+            effect_max_frames: None,
+            particle_shape: ParticleShape::Rect,
+            antialiasing: false,
+            background_color: [255.0, 255.0, 255.0],
+            background_image: None,
+            fade_color: None,
+            grayscale_threshold: 200,
+            snapshot_folder: String::from("snapshots"),
+            state_folder: String::from("states"),
+            recording_fps: 30.0,
+            threshold_mode: ThresholdMode::Fixed,
+            background_subtraction: false,
+            color_key_range: None,
+            denoise_kernel_size: 0,
+            track_all_contours: false,
+            min_contour_area: 500.0,
+            min_object_area: 0.0,
+            contour_epsilon: 0.0,
+            min_cell_coverage: 0.5,
+            processing_scale: 1.0,
+            closest_points_smoothing: 0.0,
+            min_explosion_intensity: 0.25,
+            effect_at_centroid: false,
+            live_layers_react: false,
+            explosion_hot_color: [0.0, 100.0, 255.0],
+            break_gravity: 0.5,
+            break_floor_offset: 20,
+            break_floor_restitution: 0.3,
+            break_mass_variation: false,
+            wind: [0.0, 0.0],
+            pair_interference_distances: Vec::new(),
+            draw_interference: false,
+            interference_arc: false,
+            interference_arc_jaggedness: 0.15,
+            interference_arc_subdivisions: 5,
+        }
+    }
+}
+
+impl Config {
+    // Read and validate the settings of a TOML file
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read the config file {}", path.display()))?;
+        let config = Self::parse(&content)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    // Parse and validate the settings of a TOML document
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Check that the settings are usable, naming the first invalid one
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.pixel_size > 0,
+            "pixel_size must be positive, got {}",
+            self.pixel_size
+        );
+        ensure!(
+            self.pixel_spacing >= 0,
+            "pixel_spacing must not be negative, got {}",
+            self.pixel_spacing
+        );
+        ensure!(
+            self.window_width > 0 && self.window_height > 0,
+            "window_width and window_height must be positive, got {}x{}",
+            self.window_width,
+            self.window_height
+        );
+        ensure!(
+            self.video_resolution_width > 0 && self.video_resolution_height > 0,
+            "video_resolution_width and video_resolution_height must be positive, got {}x{}",
+            self.video_resolution_width,
+            self.video_resolution_height
+        );
+        ensure!(
+            self.objects_interference_distance >= 0,
+            "objects_interference_distance must not be negative, got {}",
+            self.objects_interference_distance
+        );
+        ensure!(
+            self.webcam_capture_width > 0
+                && self.webcam_capture_height > 0
+                && self.webcam_capture_fps > 0.0,
+            "webcam_capture_width, webcam_capture_height and webcam_capture_fps must be positive"
+        );
+        ensure!(
+            self.webcam_open_attempts > 0,
+            "webcam_open_attempts must be positive, got {}",
+            self.webcam_open_attempts
+        );
+        ensure!(
+            self.capture_read_timeout_ms != Some(0),
+            "capture_read_timeout_ms must be positive, unset it to disable the timeout"
+        );
+        ensure!(
+            !self.video_extensions.is_empty(),
+            "video_extensions must not be empty"
+        );
+        ensure!(
+            self.effect_weights.iter().all(|&(_, weight)| weight >= 0.0)
+                && self.effect_weights.iter().any(|&(_, weight)| weight > 0.0),
+            "effect_weights must not be negative and at least one must be positive"
+        );
+        ensure!(
+            self.effect_weights
+                .iter()
+                .map(|&(_, weight)| weight)
+                .sum::<f64>()
+                .is_finite(),
+            "effect_weights must be finite"
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.trail_decay),
+            "trail_decay must be between 0 and 1, got {}",
+            self.trail_decay
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.size_jitter),
+            "size_jitter must be between 0 and 1, got {}",
+            self.size_jitter
+        );
+        ensure!(
+            self.effect_max_frames != Some(0),
+            "effect_max_frames must be positive, leave it out for unlimited effects"
+        );
+        ensure!(
+            (0..=255).contains(&self.grayscale_threshold),
+            "grayscale_threshold must be between 0 and 255, got {}",
+            self.grayscale_threshold
+        );
+        ensure!(
+            self.recording_fps > 0.0,
+            "recording_fps must be positive, got {}",
+            self.recording_fps
+        );
+        if let ThresholdMode::Adaptive { block_size, .. } = self.threshold_mode {
+            ensure!(
+                block_size > 1 && block_size % 2 == 1,
+                "threshold_mode block_size must be odd and greater than 1, got {}",
+                block_size
+            );
+        }
+        ensure!(
+            self.denoise_kernel_size >= 0,
+            "denoise_kernel_size must not be negative, got {}",
+            self.denoise_kernel_size
+        );
+        ensure!(
+            self.min_object_area >= 0.0,
+            "min_object_area must not be negative, got {}",
+            self.min_object_area
+        );
+        ensure!(
+            self.contour_epsilon >= 0.0,
+            "contour_epsilon must not be negative, got {}",
+            self.contour_epsilon
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.min_cell_coverage),
+            "min_cell_coverage must be between 0 and 1, got {}",
+            self.min_cell_coverage
+        );
+        ensure!(
+            self.processing_scale > 0.0 && self.processing_scale <= 1.0,
+            "processing_scale must be in (0, 1], got {}",
+            self.processing_scale
+        );
+        ensure!(
+            (0.0..1.0).contains(&self.closest_points_smoothing),
+            "closest_points_smoothing must be at least 0 and below 1, got {}",
+            self.closest_points_smoothing
+        );
+        ensure!(
+            (0.0..=1.0).contains(&self.min_explosion_intensity),
+            "min_explosion_intensity must be between 0 and 1, got {}",
+            self.min_explosion_intensity
+        );
+        ensure!(
+            self.pair_interference_distances
+                .iter()
+                .all(|&(_, _, distance)| distance >= 0),
+            "pair_interference_distances must not be negative"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_uses_the_defaults() {
+        let config = Config::parse("").unwrap();
+        assert_eq!(config.pixel_size, Config::default().pixel_size);
+        assert_eq!(config.window_name, "Window");
+    }
+
+    #[test]
+    fn keys_override_the_defaults() {
+        let config = Config::parse(
+            r#"
+            pixel_size = 6
+            boundary_mode = "Wrap"
+            effect_weights = [["Explosion", 2.0]]
+            threshold_mode = { Adaptive = { block_size = 51, c = 10.0 } }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.pixel_size, 6);
+        assert_eq!(config.boundary_mode, BoundaryMode::Wrap);
+        assert_eq!(config.effect_weights, vec![(EffectType::Explosion, 2.0)]);
+        assert_eq!(
+            config.threshold_mode,
+            ThresholdMode::Adaptive {
+                block_size: 51,
+                c: 10.0
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(Config::parse("pixel_sise = 6").is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(Config::parse("pixel_size = 0").is_err());
+        assert!(Config::parse("trail_decay = 1.5").is_err());
+        assert!(Config::parse("min_object_area = -1.0").is_err());
+        assert!(Config::parse("capture_read_timeout_ms = 0").is_err());
+        assert!(Config::parse("effect_weights = [[\"Break\", inf]]").is_err());
+        assert!(
+            Config::parse("threshold_mode = { Adaptive = { block_size = 4, c = 0.0 } }").is_err()
+        );
+    }
+}
@@ -1,123 +1,727 @@
 use anyhow::{Ok, Result};
+use log::trace;
 use opencv::{
-    core::{self, no_array, Point, Rect, VecN, Vector},
+    core::{self, no_array, Point, Ptr, Rect, Scalar, VecN, Vector},
     imgproc,
     prelude::*,
+    video::{self, BackgroundSubtractorMOG2},
 };
+use serde::Deserialize;
 
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use tokio::task;
 
+// Size of the downsampled frame used to detect unchanged frames
+const FRAME_SIGNATURE_SIZE: (i32, i32) = (64, 36);
+
+// Default share of object pixels a grid cell needs to become a particle
+const DEFAULT_MIN_CELL_COVERAGE: f64 = 0.5;
+
+// Settings of the background subtractor, the foreground is more certain above the threshold
+const BACKGROUND_HISTORY: i32 = 500;
+const BACKGROUND_VAR_THRESHOLD: f64 = 16.0;
+// MOG2 marks shadows as 127, so only the certain foreground (255) becomes an object
+const FOREGROUND_THRESHOLD: f64 = 200.0;
+
+// Contours with fewer point pairs than this are compared by brute force
+const BRUTE_FORCE_MAX_PAIRS: usize = 4096;
+// Size of the grid cells used to look up the closest points
+const CLOSEST_POINTS_CELL_SIZE: i32 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+// Enum to represent the built-in OpenCV colormaps the pixelated output can be remapped to
+pub enum ColorMap {
+    Autumn,
+    Bone,
+    Jet,
+    Winter,
+    Rainbow,
+    Ocean,
+    Summer,
+    Spring,
+    Cool,
+    Hsv,
+    Pink,
+    Hot, // Thermal look
+    Parula,
+    Magma,
+    Inferno,
+    Plasma,
+    Viridis,
+    Cividis,
+    Twilight,
+    TwilightShifted,
+    Turbo,
+    DeepGreen,
+}
+
+impl ColorMap {
+    // Get the OpenCV code of the colormap
+    fn code(self) -> i32 {
+        match self {
+            ColorMap::Autumn => imgproc::COLORMAP_AUTUMN,
+            ColorMap::Bone => imgproc::COLORMAP_BONE,
+            ColorMap::Jet => imgproc::COLORMAP_JET,
+            ColorMap::Winter => imgproc::COLORMAP_WINTER,
+            ColorMap::Rainbow => imgproc::COLORMAP_RAINBOW,
+            ColorMap::Ocean => imgproc::COLORMAP_OCEAN,
+            ColorMap::Summer => imgproc::COLORMAP_SUMMER,
+            ColorMap::Spring => imgproc::COLORMAP_SPRING,
+            ColorMap::Cool => imgproc::COLORMAP_COOL,
+            ColorMap::Hsv => imgproc::COLORMAP_HSV,
+            ColorMap::Pink => imgproc::COLORMAP_PINK,
+            ColorMap::Hot => imgproc::COLORMAP_HOT,
+            ColorMap::Parula => imgproc::COLORMAP_PARULA,
+            ColorMap::Magma => imgproc::COLORMAP_MAGMA,
+            ColorMap::Inferno => imgproc::COLORMAP_INFERNO,
+            ColorMap::Plasma => imgproc::COLORMAP_PLASMA,
+            ColorMap::Viridis => imgproc::COLORMAP_VIRIDIS,
+            ColorMap::Cividis => imgproc::COLORMAP_CIVIDIS,
+            ColorMap::Twilight => imgproc::COLORMAP_TWILIGHT,
+            ColorMap::TwilightShifted => imgproc::COLORMAP_TWILIGHT_SHIFTED,
+            ColorMap::Turbo => imgproc::COLORMAP_TURBO,
+            ColorMap::DeepGreen => imgproc::COLORMAP_DEEPGREEN,
+        }
+    }
+
+    // Get the color of the colormap for every brightness from 0 to 255
+    fn lookup_table(self) -> Result<Vec<VecN<f64, 4>>> {
+        let ramp: Vec<u8> = (0..=255).collect();
+        let ramp = Mat::from_slice(&ramp)?.try_clone()?;
+        let mut colored = Mat::default();
+        imgproc::apply_color_map(&ramp, &mut colored, self.code())?;
+
+        let mut table = Vec::with_capacity(256);
+        for brightness in 0..256 {
+            let color = colored.at_2d::<core::Vec3b>(0, brightness)?;
+            table.push(VecN::from([
+                color[0] as f64,
+                color[1] as f64,
+                color[2] as f64,
+                0.0,
+            ]));
+        }
+        Ok(table)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+// Options of the pixelation of a frame
+pub struct PixelateOptions {
+    pub adjust_size_based_on_brightness: bool, // Draw darker cells as smaller squares
+    pub parallel: bool,                        // Split the rows in chunks processed in parallel
+    pub palette: Option<ColorMap>, // Remap the cell colors by brightness for a stylized look
+}
+
+impl Default for PixelateOptions {
+    fn default() -> Self {
+        Self {
+            adjust_size_based_on_brightness: false,
+            parallel: true,
+            palette: None,
+        }
+    }
+}
+
+// Perceived brightness of an average color, BGR for color frames and the value for grayscale ones
+fn brightness(color: VecN<f64, 4>, channels: i32) -> u8 {
+    let brightness = if channels >= 3 {
+        0.114 * color[0] + 0.587 * color[1] + 0.299 * color[2]
+    } else {
+        color[0]
+    };
+    brightness.round().clamp(0.0, 255.0) as u8
+}
+
+// Compute the pixelated squares of the rows between row_start and row_end
+fn pixelate_rows(
+    input: &Mat,
+    row_start: i32,
+    row_end: i32,
+    pixel_size: i32,
+    spacing: i32,
+    adjust_size_based_on_brightness: bool,
+) -> Result<Vec<(Rect, VecN<f64, 4>)>> {
+    let rows = input.rows();
+    let cols = input.cols();
+    let mut rectangles: Vec<(Rect, VecN<f64, 4>)> = Vec::new();
+    let mut y = row_start;
+    while y < row_end && y + pixel_size <= rows {
+        let mut x = 0;
+        while x < cols && x + pixel_size <= cols {
+            // Calculate average color within the cell
+            let cell = Rect::new(x, y, pixel_size, pixel_size);
+            let sub_mat = Mat::roi(input, cell)?;
+            let avg_color = core::mean(&sub_mat, &core::no_array())?;
+
+            let rect = if adjust_size_based_on_brightness {
+                // Shrink the square around the cell center, down to a single pixel for black
+                let brightness = (avg_color[0] + avg_color[1] + avg_color[2]) / (3.0 * 255.0);
+                let size = ((pixel_size as f64 * brightness).round() as i32).clamp(1, pixel_size);
+                let offset = (pixel_size - size) / 2;
+                Rect::new(x + offset, y + offset, size, size)
+            } else {
+                cell
+            };
+            rectangles.push((rect, avg_color));
+
+            x += pixel_size + spacing;
+        }
+        y += pixel_size + spacing;
+    }
+    Ok(rectangles)
+}
+
 pub async fn pixelate_frame(
     input: &Mat,
     output: &mut Mat,
     pixel_size: i32,
     spacing: i32,
+    options: PixelateOptions,
 ) -> Result<()> {
+    let pixel_size = pixel_size.max(1);
     let rows = input.rows();
-    let cols = input.cols();
-    let num_cpus = num_cpus::get() as i32;
-    let pixels_per_row = rows / pixel_size;
-    let chunk_size = ((pixels_per_row + num_cpus - 1) / num_cpus) as i32 * pixel_size;
-
-    // Vector to hold all async tasks
-    let mut tasks = Vec::new();
-
-    let mut i = 1;
-    let mut chunk_start = 0;
-    while chunk_start < rows {
-        let input_clone = input.clone(); // Clone input matrix for each task
-        let chunk_end = (chunk_start + chunk_size).min(rows);
-
-        println!(
-            "{}: Processing chunk: {} - {} ({})",
-            i, chunk_start, chunk_end, chunk_size
-        );
-        i += 1;
-        // Spawn async task for this chunk
-        tasks.push(task::spawn(async move {
-            let mut rectangles: Vec<(Rect, VecN<f64, 4>)> = Vec::new();
-            let mut y = chunk_start;
-            while y < chunk_end && y + pixel_size <= rows {
-                let mut x = 0;
-                while x < cols && x + pixel_size <= cols {
-                    // Calculate average color within the circle's region
-                    let rect = Rect::new(x, y, pixel_size, pixel_size);
-
-                    // Get the sub-matrix of the input frame
-                    let sub_mat = Mat::roi(&input_clone, rect)?;
-                    let avg_color = core::mean(&sub_mat, &core::no_array())?;
-
-                    rectangles.push((rect, avg_color));
-
-                    x += pixel_size + spacing;
+
+    let rectangles = if options.parallel {
+        let num_cpus = num_cpus::get() as i32;
+        let pixels_per_row = rows / pixel_size;
+        // Ensure chunk_size >= pixel_size, so small frames do not loop forever
+        let chunk_size = ((pixels_per_row + num_cpus - 1) / num_cpus).max(1) * pixel_size;
+
+        // Vector to hold all async tasks
+        let mut tasks = Vec::new();
+
+        let mut chunk_start = 0;
+        while chunk_start < rows {
+            let input_clone = input.clone(); // Clone input matrix for each task
+            let chunk_end = (chunk_start + chunk_size).min(rows);
+            trace!(
+                "Processing chunk: {} - {} ({})",
+                chunk_start,
+                chunk_end,
+                chunk_size
+            );
+
+            // Spawn async task for this chunk
+            tasks.push(task::spawn(async move {
+                pixelate_rows(
+                    &input_clone,
+                    chunk_start,
+                    chunk_end,
+                    pixel_size,
+                    spacing,
+                    options.adjust_size_based_on_brightness,
+                )
+            }));
+            chunk_start = chunk_end;
+        }
+
+        // Wait for all async tasks to complete
+        let mut rectangles = Vec::new();
+        for task in tasks {
+            rectangles.append(&mut task.await??);
+        }
+        rectangles
+    } else {
+        pixelate_rows(
+            input,
+            0,
+            rows,
+            pixel_size,
+            spacing,
+            options.adjust_size_based_on_brightness,
+        )?
+    };
+
+    // Compute the palette colors once, instead of per cell
+    let lookup_table = options.palette.map(ColorMap::lookup_table).transpose()?;
+    let channels = input.channels();
+
+    for (rect, avg_color) in rectangles {
+        let color = match &lookup_table {
+            Some(table) => table[brightness(avg_color, channels) as usize],
+            None => avg_color,
+        };
+        // Draw the pixelated rectangle on the output frame
+        imgproc::rectangle(output, rect, color, -1, imgproc::LINE_8, 0)?;
+    }
+
+    Ok(())
+}
+
+// Uniform grid of the points of a contour, to find the closest point without scanning all of them
+pub(crate) struct PointGrid {
+    cell_size: i32,
+    cells: Vec<Vec<(usize, Point)>>,
+    min_cell: (i32, i32),
+    max_cell: (i32, i32),
+}
+
+impl PointGrid {
+    pub(crate) fn new(points: &[Point], cell_size: i32) -> Self {
+        let cell_size = cell_size.max(1);
+        let cell_coords: Vec<(i32, i32)> = points
+            .iter()
+            .map(|&point| Self::cell_of(point, cell_size))
+            .collect();
+        let min_cell = cell_coords.iter().fold((i32::MAX, i32::MAX), |min, cell| {
+            (min.0.min(cell.0), min.1.min(cell.1))
+        });
+        let max_cell = cell_coords.iter().fold((i32::MIN, i32::MIN), |max, cell| {
+            (max.0.max(cell.0), max.1.max(cell.1))
+        });
+
+        let mut grid = Self {
+            cell_size,
+            cells: Vec::new(),
+            min_cell,
+            max_cell,
+        };
+        if points.is_empty() {
+            return grid;
+        }
+
+        let width = (max_cell.0 - min_cell.0 + 1) as usize;
+        let height = (max_cell.1 - min_cell.1 + 1) as usize;
+        grid.cells = vec![Vec::new(); width * height];
+        for (index, (&point, cell)) in points.iter().zip(cell_coords).enumerate() {
+            let cell_index = grid.cell_index(cell);
+            grid.cells[cell_index].push((index, point));
+        }
+        grid
+    }
+
+    fn cell_of(point: Point, cell_size: i32) -> (i32, i32) {
+        (point.x.div_euclid(cell_size), point.y.div_euclid(cell_size))
+    }
+
+    fn cell_index(&self, cell: (i32, i32)) -> usize {
+        let width = self.max_cell.0 - self.min_cell.0 + 1;
+        ((cell.1 - self.min_cell.1) * width + (cell.0 - self.min_cell.0)) as usize
+    }
+
+    // Find the closest point and its squared distance
+    // On ties the point added first wins, the same as a linear scan
+    fn closest(&self, point: Point) -> Option<(i64, Point)> {
+        if self.cells.is_empty() {
+            return None;
+        }
+        let (cx, cy) = Self::cell_of(point, self.cell_size);
+
+        // Search the rings of cells around the point, starting at the first ring touching the grid
+        let first_ring = 0i32
+            .max(self.min_cell.0 - cx)
+            .max(cx - self.max_cell.0)
+            .max(self.min_cell.1 - cy)
+            .max(cy - self.max_cell.1);
+        let last_ring = (cx - self.min_cell.0)
+            .max(self.max_cell.0 - cx)
+            .max(cy - self.min_cell.1)
+            .max(self.max_cell.1 - cy);
+
+        let mut best: Option<(i64, usize, Point)> = None;
+        let visit = |cell: (i32, i32), best: &mut Option<(i64, usize, Point)>| {
+            for &(index, candidate) in &self.cells[self.cell_index(cell)] {
+                let dx = (point.x - candidate.x) as i64;
+                let dy = (point.y - candidate.y) as i64;
+                let distance = dx * dx + dy * dy;
+                let closer = best.map_or(true, |(best_distance, best_index, _)| {
+                    distance < best_distance || (distance == best_distance && index < best_index)
+                });
+                if closer {
+                    *best = Some((distance, index, candidate));
                 }
-                y += pixel_size + spacing;
             }
-            Ok(rectangles)
-        }));
-        chunk_start = chunk_end;
-    }
+        };
 
-    // Wait for all async tasks to complete and process the results
-    for task in tasks {
-        let rectangles = task.await??;
-        for (rect, avg_color) in rectangles {
-            // Draw the pixelated rectangle on the output frame
-            imgproc::rectangle(output, rect, avg_color, -1, imgproc::LINE_8, 0)?;
+        for ring in first_ring..=last_ring {
+            // Only visit the border of the ring that lies within the grid
+            for gy in (cy - ring).max(self.min_cell.1)..=(cy + ring).min(self.max_cell.1) {
+                if (gy - cy).abs() == ring {
+                    for gx in (cx - ring).max(self.min_cell.0)..=(cx + ring).min(self.max_cell.0) {
+                        visit((gx, gy), &mut best);
+                    }
+                } else {
+                    for gx in [cx - ring, cx + ring] {
+                        if gx >= self.min_cell.0 && gx <= self.max_cell.0 {
+                            visit((gx, gy), &mut best);
+                        }
+                    }
+                }
+            }
+
+            // The points of the next rings are at least ring * cell_size away
+            if let Some((best_distance, _, _)) = best {
+                let reach = ring as i64 * self.cell_size as i64;
+                if best_distance < reach * reach {
+                    break;
+                }
+            }
         }
+        best.map(|(distance, _, closest)| (distance, closest))
     }
 
-    Ok(())
+    // Get the points in the cell of the point and the 8 cells around it, with their index
+    // Covers all points closer than the cell size, along with some further away
+    pub(crate) fn near(&self, point: Point) -> impl Iterator<Item = (usize, Point)> + '_ {
+        let (cx, cy) = Self::cell_of(point, self.cell_size);
+        let (min_x, max_x) = ((cx - 1).max(self.min_cell.0), (cx + 1).min(self.max_cell.0));
+        let (min_y, max_y) = ((cy - 1).max(self.min_cell.1), (cy + 1).min(self.max_cell.1));
+        let cells = if self.cells.is_empty() {
+            Vec::new()
+        } else {
+            (min_y..=max_y)
+                .flat_map(|gy| (min_x..=max_x).map(move |gx| (gx, gy)))
+                .collect()
+        };
+        cells
+            .into_iter()
+            .flat_map(move |cell| self.cells[self.cell_index(cell)].iter().copied())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+// Enum to represent how the grayscale frame is turned into a black and white mask
+pub enum ThresholdMode {
+    Fixed,                                // Single global threshold
+    Adaptive { block_size: i32, c: f64 }, // Threshold computed per neighbourhood
 }
 
 pub struct FrameProcessor {
     masks: Vec<Mat>,
-    contours: Vec<Vector<Point>>,
+    previous_frames: Vec<Option<Mat>>,
+    contours: Vec<Vector<Vector<Point>>>,
     grayscale_threshold: f64,
+    threshold_mode: ThresholdMode,
     pixel_size: i32,
     spacing: i32,
+    min_cell_coverage: f64,
+    min_object_area: f64,
+    processing_scale: f64,
+    background_subtraction: bool,
+    background_subtractors: Vec<Option<Ptr<BackgroundSubtractorMOG2>>>,
+    contour_epsilon: f64,
 }
 
 impl FrameProcessor {
     pub fn new(pixel_size: i32, spacing: i32, grayscale_threshold: f64) -> Self {
         Self {
             masks: Vec::new(),
-            contours: Vec::<Vector<Point>>::new(),
+            previous_frames: Vec::new(),
+            contours: Vec::<Vector<Vector<Point>>>::new(),
             grayscale_threshold,
+            threshold_mode: ThresholdMode::Fixed,
             pixel_size,
             spacing,
+            min_cell_coverage: DEFAULT_MIN_CELL_COVERAGE,
+            min_object_area: 0.0,
+            processing_scale: 1.0,
+            background_subtraction: false,
+            background_subtractors: Vec::new(),
+            contour_epsilon: 0.0,
         }
     }
 
     pub fn init(&mut self, frames_amount: i32) {
         self.masks.clear();
+        self.previous_frames.clear();
         self.contours.clear();
+        self.background_subtractors.clear();
 
         for _ in 0..frames_amount {
             self.masks.push(Mat::default());
-            self.contours.push(Vector::<Point>::new());
+            self.previous_frames.push(None);
+            self.contours.push(Vector::<Vector<Point>>::new());
+            self.background_subtractors.push(None);
+        }
+    }
+
+    pub fn grayscale_threshold(&self) -> f64 {
+        self.grayscale_threshold
+    }
+
+    // Set the threshold used by the next conversion to grayscale, clamped to 0-255
+    pub fn set_grayscale_threshold(&mut self, grayscale_threshold: f64) {
+        let grayscale_threshold = grayscale_threshold.clamp(0.0, 255.0);
+        if self.grayscale_threshold != grayscale_threshold {
+            self.grayscale_threshold = grayscale_threshold;
+            (0..self.previous_frames.len()).for_each(|index| self.invalidate(index));
         }
     }
 
+    // Forget the cached frame, so the next conversion recomputes the mask
+    pub fn invalidate(&mut self, index: usize) {
+        self.previous_frames[index] = None;
+    }
+
+    // Check if the frame is identical to the previous one, using a downsampled version
+    fn is_frame_unchanged(&mut self, frame: &Mat, index: usize) -> Result<bool> {
+        let mut signature = Mat::default();
+        imgproc::resize(
+            frame,
+            &mut signature,
+            core::Size::new(FRAME_SIGNATURE_SIZE.0, FRAME_SIGNATURE_SIZE.1),
+            0.0,
+            0.0,
+            imgproc::INTER_AREA,
+        )?;
+
+        let unchanged = match &self.previous_frames[index] {
+            Some(previous) => core::norm2(&signature, previous, core::NORM_L1, &no_array())? == 0.0,
+            None => false,
+        };
+        self.previous_frames[index] = Some(signature);
+        Ok(unchanged)
+    }
+
+    pub fn set_pixel_size(&mut self, pixel_size: i32) {
+        self.pixel_size = pixel_size.max(1);
+    }
+
+    // Set the share of object pixels (0-1) a grid cell needs to become a particle
+    pub fn set_min_cell_coverage(&mut self, min_cell_coverage: f64) {
+        self.min_cell_coverage = min_cell_coverage.clamp(0.0, 1.0);
+    }
+
+    // Set the smallest area (px²) of an object, smaller blobs like camera noise are ignored
+    pub fn set_min_object_area(&mut self, min_object_area: f64) {
+        self.min_object_area = min_object_area.max(0.0);
+    }
+
+    // Set the scale of the masks relative to the frames (e.g. 0.33 for 640x360 masks of 1080p frames)
+    // The contours and object points are scaled back to the frame resolution
+    pub fn set_processing_scale(&mut self, processing_scale: f64) {
+        let processing_scale = processing_scale.clamp(0.05, 1.0);
+        if self.processing_scale != processing_scale {
+            self.processing_scale = processing_scale;
+            (0..self.previous_frames.len()).for_each(|index| self.invalidate(index));
+        }
+    }
+
+    // Set how far (in pixels) the simplified contours may deviate from the found ones
+    // Fewer contour points make the closest points search faster, 0 keeps all points
+    pub fn set_contour_epsilon(&mut self, epsilon: f64) {
+        self.contour_epsilon = epsilon.max(0.0);
+    }
+
+    // Thin the contour to the polygon within epsilon of it, keeping it unchanged for 0
+    fn simplify_contour(contour: &Vector<Point>, epsilon: f64) -> Result<Vector<Point>> {
+        if epsilon <= 0.0 {
+            return Ok(contour.clone());
+        }
+        let mut simplified = Vector::<Point>::new();
+        imgproc::approx_poly_dp(contour, &mut simplified, epsilon, true)?;
+        Ok(simplified)
+    }
+
+    // Downsample the frame to the processing scale, None if it is processed at full resolution
+    fn downscale(&self, frame: &Mat) -> Result<Option<Mat>> {
+        if self.processing_scale >= 1.0 {
+            return Ok(None);
+        }
+        let mut scaled = Mat::default();
+        imgproc::resize(
+            frame,
+            &mut scaled,
+            core::Size::default(),
+            self.processing_scale,
+            self.processing_scale,
+            imgproc::INTER_AREA,
+        )?;
+        Ok(Some(scaled))
+    }
+
+    // Convert the frame to a black and white mask, skipped if the frame hasn't changed
     pub fn convert_to_grayscale(&mut self, frame: &Mat, index: usize) -> Result<()> {
+        if self.is_frame_unchanged(frame, index)? && !self.masks[index].empty() {
+            return Ok(());
+        }
+
+        match self.threshold_mode {
+            ThresholdMode::Fixed => {
+                let scaled = self.downscale(frame)?;
+                let frame = scaled.as_ref().unwrap_or(frame);
+
+                let mut gray = Mat::default();
+                imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
+                imgproc::threshold(
+                    &gray,
+                    &mut self.masks[index],
+                    self.grayscale_threshold,
+                    255.0,
+                    imgproc::THRESH_BINARY,
+                )?;
+            }
+            ThresholdMode::Adaptive { block_size, c } => {
+                self.apply_adaptive_threshold(frame, index, block_size, c)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_threshold_mode(&mut self, threshold_mode: ThresholdMode) {
+        if self.threshold_mode != threshold_mode {
+            self.threshold_mode = threshold_mode;
+            (0..self.previous_frames.len()).for_each(|index| self.invalidate(index));
+        }
+    }
+
+    // Convert the frame to a black and white mask with a threshold computed per neighbourhood
+    // This copes with uneven lighting, where a single global threshold washes out
+    pub fn convert_to_grayscale_adaptive(
+        &mut self,
+        frame: &Mat,
+        index: usize,
+        block_size: i32,
+        c: f64,
+    ) -> Result<()> {
+        self.apply_adaptive_threshold(frame, index, block_size, c)?;
+
+        // The cached grayscale mask was overwritten
+        self.invalidate(index);
+        Ok(())
+    }
+
+    fn apply_adaptive_threshold(
+        &mut self,
+        frame: &Mat,
+        index: usize,
+        block_size: i32,
+        c: f64,
+    ) -> Result<()> {
+        // The block size must be odd and greater than 1
+        let block_size = block_size.max(3) | 1;
+
+        let scaled = self.downscale(frame)?;
+        let frame = scaled.as_ref().unwrap_or(frame);
+
         let mut gray = Mat::default();
         imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)?;
-        imgproc::threshold(
+        imgproc::adaptive_threshold(
             &gray,
             &mut self.masks[index],
-            self.grayscale_threshold,
+            255.0,
+            imgproc::ADAPTIVE_THRESH_GAUSSIAN_C,
+            imgproc::THRESH_BINARY,
+            block_size,
+            c,
+        )?;
+        Ok(())
+    }
+
+    // Build the mask from the pixels within the HSV range, as an alternative to grayscale
+    // The pixels in range are the object (black in the mask), like dark pixels in grayscale
+    pub fn extract_by_color(
+        &mut self,
+        frame: &Mat,
+        index: usize,
+        lower: Scalar,
+        upper: Scalar,
+    ) -> Result<()> {
+        let scaled = self.downscale(frame)?;
+        let frame = scaled.as_ref().unwrap_or(frame);
+
+        let mut hsv = Mat::default();
+        imgproc::cvt_color(frame, &mut hsv, imgproc::COLOR_BGR2HSV, 0)?;
+
+        let mut in_range = Mat::default();
+        core::in_range(&hsv, &lower, &upper, &mut in_range)?;
+        core::bitwise_not(&in_range, &mut self.masks[index], &no_array())?;
+
+        // The cached grayscale mask was overwritten
+        self.invalidate(index);
+        Ok(())
+    }
+
+    // Isolate the moving objects with a background model instead of their brightness
+    pub fn use_background_subtractor(&mut self) {
+        self.background_subtraction = true;
+    }
+
+    pub fn uses_background_subtractor(&self) -> bool {
+        self.background_subtraction
+    }
+
+    // Build the mask from the pixels that differ from the learned background of the layer
+    // The model learns from every frame, so objects standing still fade into the background
+    pub fn convert_with_bg_subtraction(&mut self, frame: &Mat, index: usize) -> Result<()> {
+        let scaled = self.downscale(frame)?;
+        let frame = scaled.as_ref().unwrap_or(frame);
+
+        // Each layer has its own background, created on its first frame
+        if self.background_subtractors[index].is_none() {
+            self.background_subtractors[index] = Some(video::create_background_subtractor_mog2(
+                BACKGROUND_HISTORY,
+                BACKGROUND_VAR_THRESHOLD,
+                true,
+            )?);
+        }
+        let subtractor = self.background_subtractors[index].as_mut().unwrap();
+        let mut foreground = Mat::default();
+        subtractor.apply(frame, &mut foreground, -1.0)?;
+
+        // The foreground is the object (black in the mask), like dark pixels in grayscale
+        let mut certain_foreground = Mat::default();
+        imgproc::threshold(
+            &foreground,
+            &mut certain_foreground,
+            FOREGROUND_THRESHOLD,
             255.0,
             imgproc::THRESH_BINARY,
         )?;
+        core::bitwise_not(&certain_foreground, &mut self.masks[index], &no_array())?;
+
+        // The cached grayscale mask was overwritten
+        self.invalidate(index);
+        Ok(())
+    }
+
+    // Remove the speckle noise of the mask, which would otherwise turn into stray particles
+    // Bigger kernels remove more noise but also more details of the objects
+    pub fn denoise_mask(&mut self, index: usize, kernel_size: i32) -> Result<()> {
+        let kernel = imgproc::get_structuring_element(
+            imgproc::MORPH_RECT,
+            core::Size::new(kernel_size.max(1), kernel_size.max(1)),
+            Point::new(-1, -1),
+        )?;
+
+        // The objects are black in the mask, so opening them is a closing of the mask
+        let mask = self.masks[index].clone();
+        imgproc::morphology_ex(
+            &mask,
+            &mut self.masks[index],
+            imgproc::MORPH_CLOSE,
+            &kernel,
+            Point::new(-1, -1),
+            1,
+            core::BORDER_CONSTANT,
+            imgproc::morphology_default_border_value()?,
+        )?;
         Ok(())
     }
 
     pub fn draw_mask(&self, output_frame: &mut Mat, index: usize) -> Result<()> {
+        if self.processing_scale < 1.0 && !output_frame.empty() {
+            // Scale the mask back up to the output frame
+            let size = output_frame.size()?;
+            imgproc::resize(
+                &self.masks[index],
+                output_frame,
+                size,
+                0.0,
+                0.0,
+                imgproc::INTER_NEAREST,
+            )?;
+            return Ok(());
+        }
         self.masks[index].copy_to(output_frame)?;
         Ok(())
     }
 
-    pub fn find_object_contour(&mut self, index: usize) -> Result<()> {
+    fn find_contours(&self, index: usize) -> Result<Vector<Vector<Point>>> {
         let mut inverted_mask = Mat::default();
         core::bitwise_not(&self.masks[index], &mut inverted_mask, &no_array())?;
 
@@ -131,12 +735,43 @@ impl FrameProcessor {
             Point::new(0, 0),
         )?;
 
+        // Scale the contours back to the frame resolution
+        if self.processing_scale < 1.0 {
+            let scale = 1.0 / self.processing_scale;
+            contours = contours
+                .iter()
+                .map(|contour| {
+                    contour
+                        .iter()
+                        .map(|point| {
+                            Point::new(
+                                (point.x as f64 * scale).round() as i32,
+                                (point.y as f64 * scale).round() as i32,
+                            )
+                        })
+                        .collect()
+                })
+                .collect();
+        }
+        Ok(contours)
+    }
+
+    // Keep only the largest contour of the objects in the mask
+    pub fn find_object_contour(&mut self, index: usize) -> Result<()> {
+        let contours = self.find_contours(index)?;
+
         if !contours.is_empty() {
             // Select the largest contour
-            self.contours[index] = contours
+            let largest_contour = contours
                 .iter()
                 .max_by_key(|contour| imgproc::contour_area(&contour, false).unwrap_or(0.0) as i32)
                 .unwrap();
+            if imgproc::contour_area(&largest_contour, false)? < self.min_object_area {
+                self.contours[index].clear();
+                return Ok(());
+            }
+            let simplified = Self::simplify_contour(&largest_contour, self.contour_epsilon)?;
+            self.contours[index] = Vector::from(vec![simplified]);
 
             return Ok(());
         }
@@ -144,12 +779,68 @@ impl FrameProcessor {
         Ok(())
     }
 
+    // Keep all contours of the objects in the mask with at least the given area
+    pub fn find_object_contours(&mut self, index: usize, min_area: f64) -> Result<()> {
+        let contours = self.find_contours(index)?;
+        let min_area = min_area.max(self.min_object_area);
+
+        self.contours[index] = contours
+            .iter()
+            .filter(|contour| imgproc::contour_area(&contour, false).unwrap_or(0.0) >= min_area)
+            .map(|contour| Self::simplify_contour(&contour, self.contour_epsilon))
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    // Get the bounding rectangle of the object contours, None if no object was found
+    pub fn object_bounding_rect(&self, index: usize) -> Result<Option<Rect>> {
+        let points: Vector<Point> = self.contours[index].iter().flatten().collect();
+        if points.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(imgproc::bounding_rect(&points)?))
+    }
+
+    // Get the center of mass of the object contours, None if no object was found
+    pub fn object_centroid(&self, index: usize) -> Result<Option<Point>> {
+        let contours = &self.contours[index];
+        if contours.iter().all(|contour| contour.is_empty()) {
+            return Ok(None);
+        }
+
+        // Combine the moments, so several contours are weighted by their area
+        let (mut m00, mut m10, mut m01) = (0.0, 0.0, 0.0);
+        for contour in contours.iter() {
+            let moments = imgproc::moments(&contour, false)?;
+            m00 += moments.m00;
+            m10 += moments.m10;
+            m01 += moments.m01;
+        }
+
+        if m00 > 0.0 {
+            return Ok(Some(Point::new(
+                (m10 / m00).round() as i32,
+                (m01 / m00).round() as i32,
+            )));
+        }
+
+        // Contours without an area (e.g. a line) fall back to the mean of their points
+        let points: Vec<Point> = contours.iter().flatten().collect();
+        let sum = points.iter().fold((0i64, 0i64), |sum, point| {
+            (sum.0 + point.x as i64, sum.1 + point.y as i64)
+        });
+        Ok(Some(Point::new(
+            (sum.0 / points.len() as i64) as i32,
+            (sum.1 / points.len() as i64) as i32,
+        )))
+    }
+
     pub fn draw_contours(&self, output_frame: &mut Mat) -> Result<()> {
-        for contour in &self.contours {
-            if !contour.is_empty() {
+        for contours in &self.contours {
+            if !contours.is_empty() {
                 imgproc::draw_contours(
                     output_frame,
-                    &Vector::<Vector<Point>>::from(vec![contour.clone()]),
+                    contours,
                     -1,
                     core::Scalar::new(0.0, 255.0, 0.0, 0.0),
                     2,
@@ -163,28 +854,45 @@ impl FrameProcessor {
         Ok(())
     }
 
-    // Extract object points from the mask
+    // Extract object points from the mask, one point per cell of the pixel grid
+    // that is covered enough by the object
     // Divide the work into chunks based on the rows and parallelize the processing
     pub async fn extract_object(&self, index: usize) -> Result<Vec<Point>> {
-        let rows = self.masks[index].rows();
-        let cols = self.masks[index].cols();
+        let mask_rows = self.masks[index].rows();
+        let mask_cols = self.masks[index].cols();
         let pixel_size = self.pixel_size;
-        let spacing = self.spacing;
+        let step = (pixel_size + self.spacing).max(1);
+        let min_cell_coverage = self.min_cell_coverage;
+
+        // The grid is laid out at the frame resolution, mapped onto the scaled mask
+        let scale = self.processing_scale;
+
+        // Skip masks with too few object pixels, measured at the frame resolution
+        if self.min_object_area > 0.0 && !self.masks[index].empty() {
+            let object_pixels = mask_rows * mask_cols - core::count_non_zero(&self.masks[index])?;
+            if (object_pixels as f64) / (scale * scale) < self.min_object_area {
+                return Ok(Vec::new());
+            }
+        }
+
+        let rows = (mask_rows as f64 / scale).round() as i32;
+        let cols = (mask_cols as f64 / scale).round() as i32;
+        let to_mask = move |value: i32| (value as f64 * scale) as i32;
+
+        // We will process the grid rows in steps of pixel_size + spacing
+        let row_indices: Vec<i32> = (0..rows).step_by(step as usize).collect();
+        if row_indices.is_empty() {
+            return Ok(Vec::new());
+        }
 
         // Number of CPUs for parallelization
         let num_cpus = num_cpus::get();
-        // Determine chunk size in terms of rows
-        let total_row_steps = (0..rows).step_by((pixel_size + spacing) as usize).count();
-        let chunk_size = (total_row_steps + num_cpus - 1) / num_cpus;
+        // Determine chunk size in terms of grid rows
+        let chunk_size = (row_indices.len() + num_cpus - 1) / num_cpus;
 
         let mask = self.masks[index].clone(); // Clone the mask (Mat is ref-counted)
         let mut tasks = Vec::new();
 
-        // We will process rows in steps of pixel_size + spacing
-        let row_indices: Vec<usize> = (0..rows as usize)
-            .step_by((pixel_size + spacing) as usize)
-            .collect();
-
         for chunk in row_indices.chunks(chunk_size) {
             let chunk = chunk.to_vec();
             let mask_clone = mask.clone();
@@ -192,14 +900,26 @@ impl FrameProcessor {
             let handle = tokio::task::spawn_blocking(move || {
                 let mut partial_result = Vec::new();
                 for &y in &chunk {
+                    let mask_y = to_mask(y).min(mask_rows - 1);
+                    let cell_height = to_mask(pixel_size).clamp(1, mask_rows - mask_y);
                     let mut x = 0;
                     while x < cols {
-                        // Check if the pixel at (y, x) is black
-                        let pixel_val = unsafe { *mask_clone.at_2d::<u8>(y as i32, x as i32)? };
-                        if pixel_val == 0 {
-                            partial_result.push(Point::new(x as i32, y as i32));
+                        let mask_x = to_mask(x).min(mask_cols - 1);
+                        let cell_width = to_mask(pixel_size).clamp(1, mask_cols - mask_x);
+                        let cell = Mat::roi(
+                            &mask_clone,
+                            Rect::new(mask_x, mask_y, cell_width, cell_height),
+                        )?;
+
+                        // The object is black in the mask, so count the zero pixels of the cell
+                        let cell_area = cell_width * cell_height;
+                        let object_pixels = cell_area - core::count_non_zero(&cell)?;
+                        if object_pixels > 0
+                            && object_pixels as f64 >= min_cell_coverage * cell_area as f64
+                        {
+                            partial_result.push(Point::new(x, y));
                         }
-                        x += pixel_size + spacing;
+                        x += step;
                     }
                 }
                 Ok(partial_result)
@@ -220,31 +940,48 @@ impl FrameProcessor {
 
     // Find the two closest points between two contours
     // Divide the work into chunks based on the number of points in the first contour
+    // Stops early with the first pair closer than the interference distance, since any such
+    // pair means the objects interfere
+    // Returns None if either object wasn't found
     pub async fn find_closest_points(
         &self,
         index_1: usize,
         index_2: usize,
-    ) -> Result<(Point, Point)> {
-        let contour_1 = &self.contours[index_1];
-        let contour_2 = &self.contours[index_2];
+        interference_distance: f64,
+    ) -> Result<Option<(Point, Point)>> {
+        // Gather the points of all contours, so every pair of contours is compared
+        let contour_1_data: Vec<Point> = self.contours[index_1].iter().flatten().collect();
+        let contour_2_data: Vec<Point> = self.contours[index_2].iter().flatten().collect();
 
-        if contour_1.is_empty() || contour_2.is_empty() {
-            return Ok((Point::new(0, 0), Point::new(0, 0)));
+        if contour_1_data.is_empty() || contour_2_data.is_empty() {
+            return Ok(None);
         }
 
-        // Clone data since we'll be moving them into tasks
-        let contour_1_data = contour_1.clone();
-        let contour_2_data = contour_2.clone();
+        // Bucket the points of the second contour into a grid, unless the contours are tiny
+        let grid = if contour_1_data.len() * contour_2_data.len() > BRUTE_FORCE_MAX_PAIRS {
+            Some(Arc::new(PointGrid::new(
+                &contour_2_data,
+                CLOSEST_POINTS_CELL_SIZE,
+            )))
+        } else {
+            None
+        };
+        let contour_2_data = Arc::new(contour_2_data);
 
         let num_cpus = num_cpus::get();
         // Determine chunk size for splitting contour_1
         let chunk_size = (contour_1_data.len() + num_cpus - 1) / num_cpus;
 
+        // Shared by the tasks, so all of them stop once one found interfering points
+        let interfering = Arc::new(AtomicBool::new(false));
+
         let mut tasks = Vec::new();
 
-        for chunk in contour_1_data.as_slice().chunks(chunk_size) {
+        for chunk in contour_1_data.chunks(chunk_size) {
             let chunk = chunk.to_vec();
-            let contour_2_data = contour_2_data.clone();
+            let contour_2_data = Arc::clone(&contour_2_data);
+            let grid = grid.clone();
+            let interfering = Arc::clone(&interfering);
 
             // Spawn a blocking task for each chunk
             let handle = tokio::task::spawn_blocking(move || {
@@ -253,17 +990,40 @@ impl FrameProcessor {
                 let mut local_closest_point_2 = Point::new(0, 0);
 
                 for &point_1 in &chunk {
-                    for point_2 in &contour_2_data {
-                        let dx = (point_1.x - point_2.x) as f64;
-                        let dy = (point_1.y - point_2.y) as f64;
-                        let distance = (dx * dx + dy * dy).sqrt();
-
-                        if distance < local_min_distance {
-                            local_min_distance = distance;
-                            local_closest_point_1 = point_1;
-                            local_closest_point_2 = point_2;
+                    if interfering.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    match &grid {
+                        Some(grid) => {
+                            if let Some((squared_distance, point_2)) = grid.closest(point_1) {
+                                let distance = (squared_distance as f64).sqrt();
+                                if distance < local_min_distance {
+                                    local_min_distance = distance;
+                                    local_closest_point_1 = point_1;
+                                    local_closest_point_2 = point_2;
+                                }
+                            }
+                        }
+                        None => {
+                            for &point_2 in contour_2_data.iter() {
+                                let dx = (point_1.x - point_2.x) as f64;
+                                let dy = (point_1.y - point_2.y) as f64;
+                                let distance = (dx * dx + dy * dy).sqrt();
+
+                                if distance < local_min_distance {
+                                    local_min_distance = distance;
+                                    local_closest_point_1 = point_1;
+                                    local_closest_point_2 = point_2;
+                                }
+                            }
                         }
                     }
+
+                    if local_min_distance < interference_distance {
+                        interfering.store(true, Ordering::Relaxed);
+                        break;
+                    }
                 }
                 Ok::<(f64, Point, Point)>((
                     local_min_distance,
@@ -289,6 +1049,177 @@ impl FrameProcessor {
             }
         }
 
-        Ok((global_closest_point_1, global_closest_point_2))
+        Ok(Some((global_closest_point_1, global_closest_point_2)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+    use opencv::core::Size;
+
+    // Closest point and squared distance found by scanning all points
+    fn brute_force_closest(points: &[Point], point: Point) -> (i64, Point) {
+        let mut best = (i64::MAX, Point::new(0, 0));
+        for &candidate in points {
+            let dx = (point.x - candidate.x) as i64;
+            let dy = (point.y - candidate.y) as i64;
+            if dx * dx + dy * dy < best.0 {
+                best = (dx * dx + dy * dy, candidate);
+            }
+        }
+        best
+    }
+
+    // Points of a circle outline, like the points of a contour
+    fn circle(center: Point, radius: f64, amount: usize) -> Vec<Point> {
+        (0..amount)
+            .map(|i| {
+                let angle = i as f64 / amount as f64 * std::f64::consts::TAU;
+                Point::new(
+                    center.x + (radius * angle.cos()).round() as i32,
+                    center.y + (radius * angle.sin()).round() as i32,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pipeline_finds_the_drawn_rectangle() -> Result<()> {
+        let rect = Rect::new(40, 20, 60, 40);
+        let frame = testutil::frame_with_rect(Size::new(200, 100), rect)?;
+        let mut frame_processor = FrameProcessor::new(10, 0, 128.0);
+        frame_processor.init(1);
+
+        frame_processor.convert_to_grayscale(&frame, 0)?;
+        frame_processor.find_object_contour(0)?;
+        let object = frame_processor.extract_object(0).await?;
+
+        // The contour runs through the centers of the edge pixels
+        let contour = frame_processor.contours[0].get(0)?;
+        let area = imgproc::contour_area(&contour, false)?;
+        assert_eq!(area, ((rect.width - 1) * (rect.height - 1)) as f64);
+        assert_eq!(frame_processor.object_bounding_rect(0)?, Some(rect));
+
+        // One point per 10x10 cell covered by the rectangle
+        assert_eq!(object.len(), 6 * 4);
+        assert!(object.iter().all(|point| rect.contains(*point)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_ignores_objects_below_the_min_area() -> Result<()> {
+        let frame = testutil::frame_with_rect(Size::new(200, 100), Rect::new(40, 20, 10, 10))?;
+        let mut frame_processor = FrameProcessor::new(10, 0, 128.0);
+        frame_processor.init(1);
+        frame_processor.set_min_object_area(200.0);
+
+        frame_processor.convert_to_grayscale(&frame, 0)?;
+        frame_processor.find_object_contour(0)?;
+
+        assert!(frame_processor.contours[0].is_empty());
+        assert!(frame_processor.extract_object(0).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pipeline_on_an_empty_frame_finds_nothing() -> Result<()> {
+        let frame = testutil::blank_frame(Size::new(200, 100), Scalar::all(255.0))?;
+        let mut frame_processor = FrameProcessor::new(10, 0, 128.0);
+        frame_processor.init(1);
+
+        frame_processor.convert_to_grayscale(&frame, 0)?;
+        frame_processor.find_object_contour(0)?;
+
+        assert!(frame_processor.contours[0].is_empty());
+        assert!(frame_processor.extract_object(0).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn palette_maps_dark_and_bright_cells_to_the_ends_of_the_colormap() -> Result<()> {
+        // Jet goes from dark blue to dark red (BGR)
+        let table = ColorMap::Jet.lookup_table()?;
+        assert_eq!(table.len(), 256);
+        let dark = table[brightness(VecN::from([0.0, 0.0, 0.0, 0.0]), 3) as usize];
+        let bright = table[brightness(VecN::from([255.0, 255.0, 255.0, 0.0]), 3) as usize];
+        assert!(dark[0] > dark[2], "{:?}", dark);
+        assert!(bright[2] > bright[0], "{:?}", bright);
+        Ok(())
+    }
+
+    #[test]
+    fn simplified_contour_has_fewer_points() {
+        let contour = Vector::from(circle(Point::new(400, 300), 120.0, 500));
+
+        let unchanged = FrameProcessor::simplify_contour(&contour, 0.0).unwrap();
+        assert_eq!(unchanged.len(), contour.len());
+
+        let simplified = FrameProcessor::simplify_contour(&contour, 2.0).unwrap();
+        assert!(simplified.len() >= 3);
+        assert!(
+            simplified.len() * 5 < contour.len(),
+            "{} of {} points kept",
+            simplified.len(),
+            contour.len()
+        );
+        // The simplified polygon only keeps points of the original contour
+        for point in simplified.iter() {
+            assert!(contour.iter().any(|original| original == point));
+        }
+    }
+
+    #[test]
+    fn point_grid_matches_brute_force() {
+        let contour_2 = circle(Point::new(400, 300), 120.0, 500);
+        let grid = PointGrid::new(&contour_2, CLOSEST_POINTS_CELL_SIZE);
+
+        let queries = circle(Point::new(250, 280), 90.0, 300).into_iter().chain([
+            Point::new(400, 300),
+            Point::new(-50, -50),
+            Point::new(1900, 1000),
+        ]);
+        for query in queries {
+            assert_eq!(
+                grid.closest(query),
+                Some(brute_force_closest(&contour_2, query)),
+                "query {:?}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn point_grid_prefers_the_first_point_on_ties() {
+        let points = vec![Point::new(10, 0), Point::new(-10, 0), Point::new(0, 10)];
+        let grid = PointGrid::new(&points, 4);
+        assert_eq!(
+            grid.closest(Point::new(0, 0)),
+            Some((100, Point::new(10, 0)))
+        );
+    }
+
+    #[test]
+    fn point_grid_near_covers_the_points_within_the_cell_size() {
+        let points = circle(Point::new(400, 300), 120.0, 500);
+        let grid = PointGrid::new(&points, CLOSEST_POINTS_CELL_SIZE);
+        let point = Point::new(520, 300);
+
+        let near: Vec<usize> = grid.near(point).map(|(index, _)| index).collect();
+
+        for (index, candidate) in points.iter().enumerate() {
+            let dx = (point.x - candidate.x) as f64;
+            let dy = (point.y - candidate.y) as f64;
+            if (dx * dx + dy * dy).sqrt() < CLOSEST_POINTS_CELL_SIZE as f64 {
+                assert!(near.contains(&index), "missing {:?}", candidate);
+            }
+        }
+    }
+
+    #[test]
+    fn point_grid_without_points_finds_nothing() {
+        let grid = PointGrid::new(&[], CLOSEST_POINTS_CELL_SIZE);
+        assert_eq!(grid.closest(Point::new(0, 0)), None);
     }
 }
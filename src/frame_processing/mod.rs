@@ -1,4 +1,9 @@
 pub mod frame_processing;
 
 pub use frame_processing::pixelate_frame;
+pub use frame_processing::ColorMap;
 pub use frame_processing::FrameProcessor;
+pub use frame_processing::PixelateOptions;
+pub use frame_processing::ThresholdMode;
+
+pub(crate) use frame_processing::PointGrid;
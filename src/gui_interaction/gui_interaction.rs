@@ -1,13 +1,52 @@
 use opencv::{
-    core::Point,
+    core::{self, Point, Scalar, Size, Vector},
     highgui::{self, WINDOW_NORMAL},
+    imgcodecs, imgproc,
     prelude::*,
-    Result,
+    Error, Result,
 };
-use std::sync::{Arc, Mutex};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::particle_system::EffectType;
+
+#[derive(Clone, Copy, Debug)]
+// Enum to represent the commands triggered from the keyboard
+pub enum KeyCommand {
+    Quit,                  // Exit the program
+    Pause,                 // Pause or resume the simulation
+    NextVideo,             // Switch to the next video in the folder
+    SetEffect(EffectType), // Force an effect on the active layer
+    Reset,                 // Reset the particles to their origin
+    Snapshot,              // Save the current frame as an image
+    ToggleHud,             // Show or hide the time logs on the frame
+    DumpState,             // Save the particle state as JSON
+    ExportSvg,             // Save the particle layout as SVG
+}
+
+// Key mapping of the keyboard commands
+const KEY_BINDINGS: &[(char, KeyCommand)] = &[
+    ('q', KeyCommand::Quit),
+    (' ', KeyCommand::Pause),
+    ('n', KeyCommand::NextVideo),
+    ('1', KeyCommand::SetEffect(EffectType::Push)),
+    ('2', KeyCommand::SetEffect(EffectType::Break)),
+    ('3', KeyCommand::SetEffect(EffectType::Explosion)),
+    ('r', KeyCommand::Reset),
+    ('s', KeyCommand::Snapshot),
+    ('h', KeyCommand::ToggleHud),
+    ('d', KeyCommand::DumpState),
+    ('v', KeyCommand::ExportSvg),
+];
 
 pub struct Window {
     name: String,
+    resolution: Arc<Mutex<Size>>,
+    headless: bool,
 }
 
 impl Window {
@@ -16,23 +55,176 @@ impl Window {
         highgui::resize_window(&name, width, height)?;
         Ok(Self {
             name: name.to_string(),
+            resolution: Arc::new(Mutex::new(Size::new(width, height))),
+            headless: false,
         })
     }
 
+    // Create a window without any GUI, for machines without a display
+    // Showing frames, trackbars and mouse events are no-ops and no keys are ever pressed
+    pub fn new_headless(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            resolution: Arc::new(Mutex::new(Size::default())),
+            headless: true,
+        }
+    }
+
+    // Set the resolution of the shown frames, which the mouse coordinates are kept inside
+    // The mouse callback reads it on every event, so it can change after registering it
+    pub fn set_resolution(&mut self, resolution: Size) {
+        *self.resolution.lock().unwrap() = resolution;
+    }
+
+    // Keep a point inside the frame resolution, e.g. when dragging out of the window
+    fn clamp_to_frame(point: Point, resolution: Size) -> Point {
+        if resolution.width <= 0 || resolution.height <= 0 {
+            return point;
+        }
+        Point::new(
+            point.x.clamp(0, resolution.width - 1),
+            point.y.clamp(0, resolution.height - 1),
+        )
+    }
+
+    // Write the mouse position into the shared point, in frame coordinates
     pub fn handle_mouse_events(&self, mouse_coords: Arc<Mutex<Point>>) -> Result<()> {
+        if self.headless {
+            return Ok(());
+        }
+        let resolution = Arc::clone(&self.resolution);
         let callback = Box::new(move |event: i32, x: i32, y: i32, _: i32| {
             if event == highgui::EVENT_MOUSEMOVE || event == highgui::EVENT_LBUTTONDOWN {
-                let mut coords = mouse_coords.lock().unwrap();
-                coords.x = x;
-                coords.y = y;
+                // With WINDOW_NORMAL the backends already report the position in the coordinates
+                // of the shown frame, whatever the window size, so it only needs clamping
+                let point = Self::clamp_to_frame(Point::new(x, y), *resolution.lock().unwrap());
+                *mouse_coords.lock().unwrap() = point;
             }
         });
         highgui::set_mouse_callback(&self.name, Some(callback))?;
         Ok(())
     }
 
+    // Create a trackbar that writes its position into the shared value on change
+    pub fn add_trackbar(
+        &self,
+        name: &str,
+        max: i32,
+        initial: i32,
+        shared: Arc<Mutex<i32>>,
+    ) -> Result<()> {
+        *shared.lock().unwrap() = initial;
+        if self.headless {
+            return Ok(());
+        }
+        let callback = Box::new(move |value: i32| {
+            let mut shared_value = shared.lock().unwrap();
+            *shared_value = value;
+        });
+        highgui::create_trackbar(name, &self.name, None, max, Some(callback))?;
+        highgui::set_trackbar_pos(name, &self.name, initial)?;
+        Ok(())
+    }
+
+    // Wait shortly for a key press and map it to a command
+    pub fn poll_key(&self) -> Result<Option<KeyCommand>> {
+        if self.headless {
+            return Ok(None);
+        }
+        let key = highgui::wait_key(1)?;
+        if key < 0 {
+            return Ok(None);
+        }
+
+        let key = (key & 0xFF) as u8 as char;
+        Ok(KEY_BINDINGS
+            .iter()
+            .find(|(binding, _)| *binding == key)
+            .map(|&(_, command)| command))
+    }
+
+    // Save the frame as a timestamped PNG in the directory and return its path
+    pub fn save_snapshot(&self, frame: &Mat, dir: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(dir).map_err(|e| Error::new(core::StsError, e.to_string()))?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or(0);
+        let path = dir.join(format!("snapshot_{}.png", timestamp));
+
+        if !imgcodecs::imwrite(&path.to_string_lossy(), frame, &Vector::new())? {
+            return Err(Error::new(
+                core::StsError,
+                format!("Unable to write the snapshot: {}", path.display()),
+            ));
+        }
+        Ok(path)
+    }
+
+    // Write the lines of text in the top right corner of the frame
+    pub fn draw_overlay(&self, frame: &mut Mat, lines: &[String]) -> Result<()> {
+        let font = imgproc::FONT_HERSHEY_SIMPLEX;
+        let font_scale = 0.8;
+        for (line_index, line) in lines.iter().enumerate() {
+            let mut baseline = 0;
+            let text_size = imgproc::get_text_size(line, font, font_scale, 1, &mut baseline)?;
+            let origin = Point::new(
+                frame.cols() - text_size.width - 20,
+                40 + line_index as i32 * 30,
+            );
+            // White outline, so the text is readable on any background
+            for (color, thickness) in [(Scalar::all(255.0), 4), (Scalar::all(0.0), 1)] {
+                imgproc::put_text(
+                    frame,
+                    line,
+                    origin,
+                    font,
+                    font_scale,
+                    color,
+                    thickness,
+                    imgproc::LINE_AA,
+                    false,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn show(&self, frame: &Mat) -> Result<()> {
+        if self.headless {
+            return Ok(());
+        }
         highgui::imshow(&self.name, frame)?;
         Ok(())
     }
+
+    pub fn close(&self) -> Result<()> {
+        if self.headless {
+            return Ok(());
+        }
+        highgui::destroy_window(&self.name)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_inside_the_frame_are_kept() {
+        let resolution = Size::new(1920, 1080);
+        let point = Point::new(1500, 900);
+        assert_eq!(Window::clamp_to_frame(point, resolution), point);
+    }
+
+    #[test]
+    fn points_outside_the_frame_are_clamped() {
+        let resolution = Size::new(1920, 1080);
+        assert_eq!(
+            Window::clamp_to_frame(Point::new(-5, 2000), resolution),
+            Point::new(0, 1079)
+        );
+    }
 }
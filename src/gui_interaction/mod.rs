@@ -1,3 +1,4 @@
 pub mod gui_interaction;
 
+pub use gui_interaction::KeyCommand;
 pub use gui_interaction::Window;
@@ -1,49 +1,313 @@
+mod config;
 mod frame_processing;
 mod gui_interaction;
+mod metrics;
 mod particle_system;
+#[cfg(test)]
+mod testutil;
 mod video_capture;
 
+use config::Config;
 use frame_processing::FrameProcessor;
-use gui_interaction::Window;
-use particle_system::{EffectType, ParticleSystem};
-use rand::Rng;
-use video_capture::VideoSource;
+use gui_interaction::{KeyCommand, Window};
+use metrics::{start_metrics_server, Metrics};
+use particle_system::{Background, EffectSelector, ParticleSystem};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use video_capture::{Rotation, VideoError, VideoSource, VideoWriter};
 
-use anyhow::{Ok, Result}; // Automatically handle the error types
+use anyhow::{anyhow, Ok, Result}; // Automatically handle the error types
+use clap::{ArgAction, Parser};
+use log::{debug, error, info, warn};
 use opencv::{
-    core::{self, Point},
-    highgui::wait_key,
-    imgproc,
+    core::{self, Point, Rect, Scalar, Vector},
+    imgcodecs, imgproc,
     prelude::*,
 };
 
-use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 // Define the constants
-const PIXEL_SIZE: i32 = 10; // Define maximum possible pixel size
-const PIXEL_SPACING: i32 = 0; // Define the spacing between pixels
-const WINDOW_NAME: &str = "Window"; // Define the name of the window
-const WINDOW_WIDTH: i32 = 960; // Define the width of the window
-const WINDOW_HEIGHT: i32 = 540; // Define the height of the window
-const VIDEO_RESOLUTION_WIDTH: i32 = 1920; // Define the width of the video resolution
-const VIDEO_RESOLUTION_HEIGHT: i32 = 1080; // Define the height of the video resolution
-const OBJECTS_INTERFERENCE_DISTANCE: i32 = 10; // Define the distance to detect interference
-const WEBCAM_CONTRAST: f64 = 1.0; // Define the video contrast
-const WEBCAM_BRIGHTNESS: f64 = 90.0; // Define the video brightness
+const PUSH_AREA_PER_DISTANCE: i32 = 1000; // Define the squared push radius (px²) per unit of interference distance
+const EFFECT_WEIGHT_STEPS: f64 = 10.0; // Define the trackbar steps per unit of effect weight
 
-fn detect_interference(
+// Kind of the main video sources
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SourceKind {
+    Webcam,
+    File,
+    Stream,
+}
+
+// Main video sources given as <kind>:<value>[,<value>...], one layer per value
+#[derive(Clone, Debug)]
+struct SourceArg {
+    kind: SourceKind,
+    values: Vec<String>,
+}
+
+fn parse_source(arg: &str) -> Result<SourceArg> {
+    let (kind, values) = arg
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected <webcam|file|stream>:<value>, got {}", arg))?;
+    let kind = match kind {
+        "webcam" => SourceKind::Webcam,
+        "file" => SourceKind::File,
+        "stream" => SourceKind::Stream,
+        _ => return Err(anyhow!("unknown source kind {}", kind)),
+    };
+    let values: Vec<String> = values.split(',').map(|value| value.to_string()).collect();
+    if values.iter().any(|value| value.is_empty()) {
+        return Err(anyhow!("empty source value in {}", arg));
+    }
+    Ok(SourceArg { kind, values })
+}
+
+fn parse_rotation(arg: &str) -> Result<Rotation> {
+    arg.parse::<u32>()
+        .ok()
+        .and_then(Rotation::from_degrees)
+        .ok_or_else(|| anyhow!("expected 90, 180 or 270, got {}", arg))
+}
+
+// Command line arguments
+#[derive(Parser, Debug)]
+#[command(about = "Turn the objects of videos into interacting particles")]
+struct Cli {
+    #[arg(
+        long,
+        value_parser = parse_source,
+        help = "Main video sources, e.g. webcam:0, file:data/punch.mp4 or stream:rtsp://host/stream, comma separated values give each source its own layer"
+    )]
+    source: SourceArg,
+
+    #[arg(long, help = "Folder with the videos played as clips")]
+    folder: PathBuf,
+
+    #[arg(
+        long,
+        help = "TOML file with the settings, missing settings keep their default"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Count,
+        help = "Log the interferences, twice to also log the time logs"
+    )]
+    verbose: u8,
+
+    #[arg(long, help = "Record the output to an MP4 file")]
+    record: Option<String>,
+
+    #[arg(long, help = "Run without a window, the input videos are not looped")]
+    headless: bool,
+
+    #[arg(long, help = "Stop after processing this amount of frames")]
+    max_frames: Option<u64>,
+
+    #[arg(long, help = "Show the amount of particles per layer")]
+    show_stats: bool,
+
+    #[arg(
+        long,
+        help = "Check that the sources, the folder and the recorder work, then exit"
+    )]
+    check: bool,
+
+    #[arg(
+        long,
+        help = "Serve the loop time, FPS, particle count and current effect at http://<host>:<port>/metrics"
+    )]
+    metrics_port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Seed the random effects, so the same input plays the same effects, e.g. to record identical takes"
+    )]
+    seed: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = parse_rotation,
+        help = "Rotate the captured frames clockwise by 90, 180 or 270 degrees, e.g. for a portrait display"
+    )]
+    rotate: Option<Rotation>,
+}
+
+// Interference distances for specific pairs of objects, with a default for the others
+struct InterferenceDistances {
+    default_distance: i32,
+    pair_distances: HashMap<(usize, usize), i32>,
+}
+
+impl InterferenceDistances {
+    fn new(default_distance: i32) -> Self {
+        Self {
+            default_distance,
+            pair_distances: HashMap::new(),
+        }
+    }
+
+    // Pairs are unordered, so (a, b) and (b, a) share the same distance
+    fn pair_key(index_1: usize, index_2: usize) -> (usize, usize) {
+        (index_1.min(index_2), index_1.max(index_2))
+    }
+
+    fn set_default(&mut self, distance: i32) {
+        self.default_distance = distance;
+    }
+
+    fn set(&mut self, index_1: usize, index_2: usize, distance: i32) {
+        self.pair_distances
+            .insert(Self::pair_key(index_1, index_2), distance);
+    }
+
+    fn get(&self, index_1: usize, index_2: usize) -> i32 {
+        *self
+            .pair_distances
+            .get(&Self::pair_key(index_1, index_2))
+            .unwrap_or(&self.default_distance)
+    }
+}
+
+// Options for drawing the connection between the two closest points
+#[derive(Clone, Copy, Debug)]
+struct InterferenceStyle {
+    arc: bool,         // Draw a jittery electric arc instead of a straight line
+    jaggedness: f64,   // Maximum midpoint offset relative to the segment length
+    subdivisions: u32, // How many times the arc is subdivided
+    seed: u64,         // Seed for the arc perturbation (change it every frame to animate)
+}
+
+// Build the polyline of an electric arc between two points by midpoint displacement
+fn electric_arc_points(
     point_1: Point,
     point_2: Point,
+    jaggedness: f64,
+    subdivisions: u32,
+    seed: u64,
+) -> Vector<Point> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut points = vec![
+        (point_1.x as f64, point_1.y as f64),
+        (point_2.x as f64, point_2.y as f64),
+    ];
+
+    let dx = (point_2.x - point_1.x) as f64;
+    let dy = (point_2.y - point_1.y) as f64;
+    let mut max_offset = (dx * dx + dy * dy).sqrt() * jaggedness.max(0.0);
+
+    for _ in 0..subdivisions {
+        let mut subdivided = Vec::with_capacity(points.len() * 2 - 1);
+        for segment in points.windows(2) {
+            let (ax, ay) = segment[0];
+            let (bx, by) = segment[1];
+            let length = ((bx - ax) * (bx - ax) + (by - ay) * (by - ay))
+                .sqrt()
+                .max(1.0);
+
+            // Move the midpoint along the normal of the segment
+            let offset = rng.gen_range(-max_offset..=max_offset);
+            let normal = (-(by - ay) / length, (bx - ax) / length);
+            subdivided.push(segment[0]);
+            subdivided.push((
+                (ax + bx) / 2.0 + normal.0 * offset,
+                (ay + by) / 2.0 + normal.1 * offset,
+            ));
+        }
+        subdivided.push(points[points.len() - 1]);
+        points = subdivided;
+        max_offset /= 2.0;
+    }
+
+    points
+        .iter()
+        .map(|&(x, y)| Point::new(x.round() as i32, y.round() as i32))
+        .collect()
+}
+
+fn draw_connection(
     output: &mut Mat,
-    draw: bool,
-) -> Result<bool> {
-    if point_1.x == 0 && point_1.y == 0 && point_2.x == 0 && point_2.y == 0 {
-        return Ok(false);
+    point_1: Point,
+    point_2: Point,
+    color: Scalar,
+    style: &InterferenceStyle,
+) -> Result<()> {
+    if style.arc {
+        let arc = electric_arc_points(
+            point_1,
+            point_2,
+            style.jaggedness,
+            style.subdivisions,
+            style.seed,
+        );
+        imgproc::polylines(
+            output,
+            &Vector::<Vector<Point>>::from(vec![arc]),
+            false,
+            color,
+            2,
+            imgproc::LINE_AA,
+            0,
+        )?;
+    } else {
+        imgproc::line(output, point_1, point_2, color, 2, imgproc::LINE_AA, 0)?;
     }
+    Ok(())
+}
+
+// Result of the interference detection between two closest points
+#[derive(Clone, Copy, Debug)]
+struct Interference {
+    triggered: bool, // The points are closer than the interference distance
+    distance: f64,   // Distance between the points
+    midpoint: Point, // Point halfway between the points
+}
+
+fn midpoint(point_1: Point, point_2: Point) -> Point {
+    Point::new((point_1.x + point_2.x) / 2, (point_1.y + point_2.y) / 2)
+}
+
+// Move the point towards the new value with an exponential moving average, where the factor
+// is the share of the previous value kept (0 for no smoothing)
+fn smooth_point(previous: Point, current: Point, factor: f64) -> Point {
+    let factor = factor.clamp(0.0, 1.0);
+    Point::new(
+        (previous.x as f64 * factor + current.x as f64 * (1.0 - factor)).round() as i32,
+        (previous.y as f64 * factor + current.y as f64 * (1.0 - factor)).round() as i32,
+    )
+}
+
+// Check if the closest points of two objects interfere, None if either object wasn't found
+fn detect_interference(
+    closest_points: Option<(Point, Point)>,
+    interference_distance: i32,
+    output: &mut Mat,
+    draw: bool,
+    style: &InterferenceStyle,
+) -> Result<Interference> {
+    let Some((point_1, point_2)) = closest_points else {
+        return Ok(Interference {
+            triggered: false,
+            distance: f64::MAX,
+            midpoint: Point::new(0, 0),
+        });
+    };
 
     let dx = (point_1.x - point_2.x) as f64;
     let dy = (point_1.y - point_2.y) as f64;
-    let distance = (dx * dx + dy * dy).sqrt() as i32;
+    let distance = (dx * dx + dy * dy).sqrt();
+    let interference_distance = interference_distance as f64;
 
     if draw {
         // Draw points and a line between the two closest points
@@ -66,131 +330,674 @@ fn detect_interference(
             0,
         )?;
 
-        if distance < OBJECTS_INTERFERENCE_DISTANCE {
-            imgproc::line(
+        if distance < interference_distance {
+            draw_connection(
                 output,
                 point_1,
                 point_2,
                 core::Scalar::new(0.0, 0.0, 255.0, 0.0),
-                2,
-                imgproc::LINE_AA,
-                0,
+                style,
             )?;
         } else {
-            imgproc::line(
+            draw_connection(
                 output,
                 point_1,
                 point_2,
                 core::Scalar::new(255.0, 0.0, 0.0, 0.0),
-                2,
-                imgproc::LINE_AA,
-                0,
+                style,
             )?;
         }
     }
 
-    Ok(distance < OBJECTS_INTERFERENCE_DISTANCE)
+    Ok(Interference {
+        triggered: distance < interference_distance,
+        distance,
+        midpoint: midpoint(point_1, point_2),
+    })
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 4 {
-        println!(
-            "Usage: {} [webcam <webcam_index> | file <video_path_1>] <folder_for_video_sources> [print_info | print_time_logs]",
-            args[0]
-        );
-        return Ok(());
+// Read all video files with one of the extensions from the folder, sorted to have a consistent order
+fn scan_video_folder(folder: &Path, extensions: &[String]) -> Result<Vec<PathBuf>> {
+    let mut video_files = fs::read_dir(folder)?
+        .filter_map(|entry| entry.ok())
+        .filter(|e| e.file_type().map(|ft| ft.is_file()).unwrap_or(false))
+        .map(|e| e.path())
+        .filter(|p| {
+            // Filter for the video extensions, ignoring the case (e.g. .MOV)
+            p.extension().map_or(false, |ext| {
+                extensions
+                    .iter()
+                    .any(|extension| ext.eq_ignore_ascii_case(extension))
+            })
+        })
+        .collect::<Vec<PathBuf>>();
+    video_files.sort();
+    Ok(video_files)
+}
+
+// Open the first video that plays, starting at the given index and skipping broken files
+// Returns the index of the opened video, or None if none of them plays
+async fn open_next_video(
+    video_source: &mut VideoSource,
+    video_files: &[PathBuf],
+    start_index: usize,
+) -> Option<usize> {
+    for offset in 0..video_files.len() {
+        let index = (start_index + offset) % video_files.len();
+        let video_path = video_files[index].to_string_lossy().to_string();
+        if video_source.set_source_file(&video_path).is_ok()
+            && video_source.update_frame().await.unwrap_or(false)
+        {
+            return Some(index);
+        }
+        warn!("Skipping the video file that fails to play: {}", video_path);
+    }
+    None
+}
+
+// Write the particle state as a timestamped JSON file in the directory and return its path
+fn save_state(state: &serde_json::Value, dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    let path = dir.join(format!("state_{}.json", timestamp));
+
+    fs::write(&path, serde_json::to_string_pretty(state)?)?;
+    Ok(path)
+}
+
+// Save the particle layout as a timestamped SVG in the directory and return its path
+fn export_layout(particle_system: &ParticleSystem, dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or(0);
+    let path = dir.join(format!("layout_{}.svg", timestamp));
+
+    particle_system.export_svg(&path)?;
+    Ok(path)
+}
+
+// Get the index of the video after the current one, or None if there are no videos
+fn next_video_index(current_index: usize, videos_amount: usize) -> Option<usize> {
+    if videos_amount == 0 {
+        return None;
+    }
+    Some((current_index + 1) % videos_amount)
+}
+
+// Open a main source and read its first frame, without any of the runtime settings
+async fn check_source(
+    kind: SourceKind,
+    value: &str,
+    config: &Config,
+    rotation: Option<Rotation>,
+) -> Result<()> {
+    let mut video_source = VideoSource::new((
+        config.video_resolution_width,
+        config.video_resolution_height,
+    ))?;
+    video_source.set_rotation(rotation);
+    video_source.set_read_timeout(config.capture_read_timeout_ms.map(Duration::from_millis));
+    match kind {
+        SourceKind::Webcam => video_source.set_source_webcam_retry(
+            value.parse::<i32>()?,
+            config.webcam_open_attempts,
+            Duration::from_millis(config.webcam_open_retry_delay_ms),
+        )?,
+        SourceKind::Stream => video_source.set_source_stream(value)?,
+        SourceKind::File => video_source.set_source_file(&value.to_string())?,
+    }
+    if let Some([x, y, width, height]) = config.roi {
+        video_source.set_roi(Rect::new(x, y, width, height))?;
+    }
+    if !video_source.update_frame().await? || video_source.current_frame().empty() {
+        return Err(anyhow!("no frame could be read"));
+    }
+    video_source.release()?;
+    Ok(())
+}
+
+// Open the recorder once to check the output path is writable, removing the test file
+fn check_recorder(path: &str, config: &Config, rotation: Option<Rotation>) -> Result<()> {
+    let existed = Path::new(path).exists();
+    let resolution = (
+        config.video_resolution_width,
+        config.video_resolution_height,
+    );
+    let (width, height) = match rotation {
+        Some(rotation) => rotation.rotate_size(resolution),
+        None => resolution,
+    };
+    let mut video_writer =
+        VideoWriter::open(path, config.recording_fps, core::Size::new(width, height))?;
+    video_writer.release()?;
+    if !existed {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// Check the sources, the clip folder and the recorder before a show, logging a summary
+// Returns true if everything works
+async fn run_preflight_check(cli: &Cli, config: &Config) -> bool {
+    let mut ok = true;
+
+    for value in &cli.source.values {
+        if let Err(error) = check_source(cli.source.kind, value, config, cli.rotate).await {
+            error!("Source {:?} {}: {}", cli.source.kind, value, error);
+            ok = false;
+        } else {
+            info!("Source {:?} {}: ok", cli.source.kind, value);
+        }
+    }
+
+    match scan_video_folder(&cli.folder, &config.video_extensions) {
+        Result::Ok(video_files) => {
+            let mut playable = 0;
+            for video_file in &video_files {
+                let value = video_file.to_string_lossy();
+                if let Err(error) = check_source(SourceKind::File, &value, config, cli.rotate).await
+                {
+                    warn!("Clip {}: {}", value, error);
+                } else {
+                    playable += 1;
+                }
+            }
+            if playable == 0 {
+                error!(
+                    "Folder {}: no playable clips out of {} files",
+                    cli.folder.display(),
+                    video_files.len()
+                );
+                ok = false;
+            } else {
+                info!(
+                    "Folder {}: {} of {} clips play",
+                    cli.folder.display(),
+                    playable,
+                    video_files.len()
+                );
+            }
+        }
+        Err(error) => {
+            error!("Folder {}: {}", cli.folder.display(), error);
+            ok = false;
+        }
     }
 
-    // Initialize the first video source
-    let mut video_source_1 = VideoSource::new((VIDEO_RESOLUTION_WIDTH, VIDEO_RESOLUTION_HEIGHT))?;
-    if args[1] == "webcam" {
-        video_source_1.set_source_webcam(args[2].parse::<i32>()?)?;
-        video_source_1.set_contrast(WEBCAM_CONTRAST);
-        video_source_1.set_brightness(WEBCAM_BRIGHTNESS);
+    if let Some(path) = &cli.record {
+        if let Err(error) = check_recorder(path, config, cli.rotate) {
+            error!("Recorder {}: {}", path, error);
+            ok = false;
+        } else {
+            info!("Recorder {}: ok", path);
+        }
+    }
+
+    if ok {
+        info!("Check passed");
     } else {
-        video_source_1.set_source_file(&args[2])?;
+        error!("Check failed");
     }
-    video_source_1.update_frame()?;
+    ok
+}
 
-    // Read all video files from the folder specified in args[3]
-    let video_folder = std::path::Path::new(&args[3]);
-    let mut video_files = fs::read_dir(video_folder)?
-        .filter_map(|entry| entry.ok())
-        .filter(|e| e.file_type().map(|ft| ft.is_file()).unwrap_or(false))
-        .map(|e| e.path())
-        .filter(|p| p.extension().map_or(false, |ext| ext == "mp4")) // Filter for .mp4 files
-        .collect::<Vec<PathBuf>>();
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
 
-    // Sort files to have a consistent order
-    video_files.sort();
+    // Log at the info level by default, the verbose flag enables the debug logs
+    // The RUST_LOG environment variable overrides the levels
+    let mut log_filter = String::from("info");
+    if cli.verbose >= 1 {
+        log_filter.push_str(",interference=debug");
+    }
+    if cli.verbose >= 2 {
+        log_filter.push_str(",time_logs=debug");
+    }
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_filter)).init();
+
+    let headless = cli.headless;
+
+    // Load the settings, falling back to the defaults without a config file
+    let config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    // Only check the inputs and outputs, with a nonzero exit code on failure
+    if cli.check {
+        let passed = run_preflight_check(&cli, &config).await;
+        std::process::exit(if passed { 0 } else { 1 });
+    }
 
-    if video_files.is_empty() {
-        eprintln!("No video files found in the specified folder: {}", args[3]);
-        return Ok(());
+    // Initialize the live video sources, one layer per webcam index or file path
+    let live_sources_args = &cli.source.values;
+    let mut video_sources = Vec::new();
+    for source_arg in live_sources_args {
+        let mut video_source = VideoSource::new((
+            config.video_resolution_width,
+            config.video_resolution_height,
+        ))?;
+        video_source.set_rotation(cli.rotate);
+        video_source.set_read_timeout(config.capture_read_timeout_ms.map(Duration::from_millis));
+        if cli.source.kind == SourceKind::Webcam {
+            video_source.set_source_webcam_retry(
+                source_arg.parse::<i32>()?,
+                config.webcam_open_attempts,
+                Duration::from_millis(config.webcam_open_retry_delay_ms),
+            )?;
+            let (width, height, fps) = video_source.set_capture_properties(
+                config.webcam_capture_width,
+                config.webcam_capture_height,
+                config.webcam_capture_fps,
+            )?;
+            info!(
+                "Webcam {} captures at {}x{} ({} FPS)",
+                source_arg, width, height, fps
+            );
+            video_source.set_contrast(config.webcam_contrast);
+            video_source.set_brightness(config.webcam_brightness);
+            video_source.set_flip(config.webcam_flip);
+        } else if cli.source.kind == SourceKind::Stream {
+            // A stream that isn't up yet is retried while running, instead of exiting
+            match video_source.set_source_stream(source_arg) {
+                Err(error @ VideoError::OpenFailed(_)) => {
+                    warn!("{}, retrying while running", error);
+                    video_source.set_blank_frame()?;
+                }
+                result => result?,
+            }
+        } else {
+            video_source.set_source_file(source_arg)?;
+            video_source.set_contrast(config.file_contrast);
+            video_source.set_brightness(config.file_brightness);
+
+            // Without a window, the run ends with the input video
+            video_source.set_loop(!headless);
+        }
+        if let Some([x, y, width, height]) = config.roi {
+            video_source.set_roi(Rect::new(x, y, width, height))?;
+        }
+        video_source.update_frame().await?;
+        video_sources.push(video_source);
     }
 
-    // Initialize second video source with the first video in the folder
-    let mut video_source_2 = VideoSource::new((VIDEO_RESOLUTION_WIDTH, VIDEO_RESOLUTION_HEIGHT))?;
+    // Read all video files from the clip folder
+    let video_folder = cli.folder.as_path();
+    let mut video_files =
+        scan_video_folder(video_folder, &config.video_extensions).unwrap_or_else(|error| {
+            error!(
+                "Unable to read the video folder {}: {}",
+                video_folder.display(),
+                error
+            );
+            Vec::new()
+        });
+
+    // Initialize the clip video source (last layer) with the first video in the folder that plays
+    let mut clip_source = VideoSource::new((
+        config.video_resolution_width,
+        config.video_resolution_height,
+    ))?;
+    clip_source.set_rotation(cli.rotate);
     let mut current_video_index = 0;
-    video_source_2.set_source_file(
-        &video_files[current_video_index]
-            .to_str()
-            .unwrap()
-            .to_string(),
-    )?;
-    video_source_2.update_frame()?;
+    let mut waiting_for_sources = false;
+    clip_source.set_contrast(config.file_contrast);
+    clip_source.set_brightness(config.file_brightness);
+    clip_source.set_crossfade_frames(config.clip_crossfade_frames);
+    match open_next_video(&mut clip_source, &video_files, 0).await {
+        Some(index) => current_video_index = index,
+        None => {
+            warn!(
+                "No playable video files found in the specified folder: {}, falling back to the primary source",
+                video_folder.display()
+            );
+            if !video_sources[0].is_live() {
+                // Loop the primary video as the clip
+                clip_source.set_source_file(&live_sources_args[0])?;
+                clip_source.set_loop(true);
+                clip_source.update_frame().await?;
+            } else {
+                // A webcam or stream can't be opened twice, show an empty clip instead
+                clip_source.set_blank_frame()?;
+            }
+            waiting_for_sources = true;
+        }
+    }
+    video_sources.push(clip_source);
+
+    let layers_amount = video_sources.len();
+    let clip_layer = layers_amount - 1;
 
     // Initialize the particle system effect
-    let mut particle_system = ParticleSystem::new(
-        video_source_1.frame.lock().unwrap().size()?,
-        PIXEL_SIZE,
-        PIXEL_SPACING,
-        OBJECTS_INTERFERENCE_DISTANCE * 1000,
-    );
-    particle_system.init(&video_source_1.frame.lock().unwrap(), 2)?;
+    let (frame_width, frame_height) = video_sources[0].output_resolution();
+    let mut particle_system = ParticleSystem::builder()
+        .window_size(core::Size::new(frame_width, frame_height))
+        .pixel_size(config.pixel_size)
+        .pixel_spacing(config.pixel_spacing)
+        .interference_distance(config.objects_interference_distance * PUSH_AREA_PER_DISTANCE)
+        .build()?;
+    particle_system.init(&video_sources[0].current_frame(), layers_amount as i32)?;
+    particle_system.set_snap_to_grid(config.snap_to_grid);
+    particle_system.set_boundary_mode(config.boundary_mode);
+    particle_system.set_trail_decay(config.trail_decay);
+    particle_system.set_size_jitter(config.size_jitter);
+    if let Some(seed) = cli.seed {
+        particle_system.set_seed(seed);
+    }
+    particle_system.set_spawn_frames(config.spawn_frames);
+    particle_system.set_leave_frames(config.leave_frames);
+    for index in 0..layers_amount {
+        particle_system.set_effect_max_frames(index, config.effect_max_frames)?;
+    }
+    particle_system.set_collision(config.particle_collision);
+    particle_system.set_particle_shape(config.particle_shape);
+    particle_system.set_antialiasing(config.antialiasing);
+    particle_system.set_hot_color(Scalar::new(
+        config.explosion_hot_color[0],
+        config.explosion_hot_color[1],
+        config.explosion_hot_color[2],
+        0.0,
+    ));
+    particle_system.set_gravity(config.break_gravity);
+    particle_system.set_floor_offset(config.break_floor_offset);
+    particle_system.set_floor_restitution(config.break_floor_restitution);
+    particle_system.set_wind(config.wind[0], config.wind[1]);
+    particle_system.set_mass_variation(config.break_mass_variation);
+    match &config.background_image {
+        Some(path) => {
+            let image = imgcodecs::imread(path, imgcodecs::IMREAD_COLOR)?;
+            particle_system.set_background(Background::Image(image))?;
+        }
+        None => particle_system.set_background(Background::Color(Scalar::new(
+            config.background_color[0],
+            config.background_color[1],
+            config.background_color[2],
+            0.0,
+        )))?,
+    }
+    if let Some(fade_color) = config.fade_color {
+        particle_system.set_fade_target(Some(Scalar::new(
+            fade_color[0],
+            fade_color[1],
+            fade_color[2],
+            0.0,
+        )));
+    }
+    for index in 0..clip_layer {
+        particle_system.set_reacts_to_interference(index, config.live_layers_react)?;
+    }
+    particle_system.set_swirl_clockwise(config.swirl_clockwise);
+    particle_system.set_reassembly_speed(config.reassembly_speed);
 
     // Initialize the frame processor
-    let mut frame_processor = FrameProcessor::new(PIXEL_SIZE, PIXEL_SPACING, 200.0);
-    frame_processor.init(2);
+    let mut frame_processor = FrameProcessor::new(
+        config.pixel_size,
+        config.pixel_spacing,
+        config.grayscale_threshold as f64,
+    );
+    frame_processor.init(layers_amount as i32);
+    frame_processor.set_threshold_mode(config.threshold_mode);
+    frame_processor.set_min_cell_coverage(config.min_cell_coverage);
+    frame_processor.set_min_object_area(config.min_object_area);
+    frame_processor.set_processing_scale(config.processing_scale);
+    frame_processor.set_contour_epsilon(config.contour_epsilon);
+    if config.background_subtraction {
+        frame_processor.use_background_subtractor();
+    }
+
+    // Initialize the recorder of the output frames
+    let mut video_writer = match &cli.record {
+        Some(path) => Some(VideoWriter::open(
+            path,
+            config.recording_fps,
+            particle_system.output_frame.size()?,
+        )?),
+        None => None,
+    };
 
     // Initialize GUI window and mouse events
-    let window = Window::new(WINDOW_NAME, WINDOW_WIDTH, WINDOW_HEIGHT)?;
+    let mut window = if headless {
+        if video_writer.is_none() {
+            warn!("Running headless without recording, the output is not saved");
+        }
+        Window::new_headless(&config.window_name)
+    } else {
+        // Turn the window with the frames, so they aren't stretched
+        let window_size = (config.window_width, config.window_height);
+        let (width, height) = match cli.rotate {
+            Some(rotation) => rotation.rotate_size(window_size),
+            None => window_size,
+        };
+        Window::new(&config.window_name, width, height)?
+    };
+
+    // The mouse position in frame coordinates, negative until the mouse enters the window
+    let mouse_coords = Arc::new(Mutex::new(Point::new(-1, -1)));
+    window.set_resolution(particle_system.output_frame.size()?);
+    window.handle_mouse_events(Arc::clone(&mouse_coords))?;
+
+    // Initialize the trackbars to tune the settings at runtime
+    let grayscale_threshold = Arc::new(Mutex::new(config.grayscale_threshold));
+    let interference_distance = Arc::new(Mutex::new(config.objects_interference_distance));
+    let pixel_size = Arc::new(Mutex::new(config.pixel_size));
+    window.add_trackbar(
+        "Threshold",
+        255,
+        config.grayscale_threshold,
+        Arc::clone(&grayscale_threshold),
+    )?;
+    window.add_trackbar(
+        "Interference",
+        100,
+        config.objects_interference_distance,
+        Arc::clone(&interference_distance),
+    )?;
+    window.add_trackbar("Pixel size", 50, config.pixel_size, Arc::clone(&pixel_size))?;
+
+    let mut objects: Vec<Vec<Point>> = vec![Vec::new(); layers_amount];
+
+    // The smoothed closest points of every pair of objects in the previous loop
+    let mut smoothed_points: HashMap<(usize, usize), (Point, Point)> = HashMap::new();
 
-    let mut object_1: Vec<Point> = Vec::new();
-    let mut object_2: Vec<Point> = Vec::new();
-    let mut point_1 = Point::new(0, 0);
-    let mut point_2 = Point::new(0, 0);
+    // The pair of layers that triggered the current effect
+    let mut interfering_pair = (0, clip_layer);
+    let mut effect_intensity = 1.0;
+
+    // Initialize the interference distances between the objects
+    let mut interference_distances =
+        InterferenceDistances::new(config.objects_interference_distance);
+    for &(index_1, index_2, distance) in &config.pair_interference_distances {
+        interference_distances.set(index_1, index_2, distance);
+    }
+
+    // Initialize the weighted selection of the interference effects
+    let mut effect_selector = EffectSelector::new(&config.effect_weights);
+
+    // One trackbar per effect weight, with the last applied position
+    let mut effect_weight_trackbars = Vec::new();
+    for &(effect_type, weight) in effect_selector.weights() {
+        let position = (weight * EFFECT_WEIGHT_STEPS).round() as i32;
+        let shared_weight = Arc::new(Mutex::new(position));
+        window.add_trackbar(
+            &format!("{:?} weight", effect_type),
+            100,
+            position,
+            Arc::clone(&shared_weight),
+        )?;
+        effect_weight_trackbars.push((effect_type, shared_weight, position));
+    }
+    let mut effect_rng = match cli.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    // Expose the metrics for monitoring, if enabled
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    if let Some(port) = cli.metrics_port {
+        start_metrics_server(port, Arc::clone(&metrics)).await?;
+    }
+    let mut empty_frames: u64 = 0;
+    let mut live_sources_delivering = vec![true; clip_layer];
 
     let mut start_next_video = false;
+    let mut paused = false;
+    let mut paused_frame = Mat::default();
+    let mut pause_start = std::time::Instant::now();
+    let mut last_idle_log = pause_start;
+    let mut show_hud = false;
+    let mut hud_lines: Vec<String> = Vec::new();
+    let mut frame_index: u64 = 0;
+    let mut total_loop_time = Duration::ZERO;
+    let mut previous_loop_start = std::time::Instant::now();
+
+    // Stop the loop on Ctrl-C, so the webcams and the window are released
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupted_clone = Arc::clone(&interrupted);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            interrupted_clone.store(true, Ordering::SeqCst);
+        }
+    });
 
     loop {
+        if interrupted.load(Ordering::SeqCst) {
+            info!("Interrupted");
+            break;
+        }
+
+        // Keep the window responsive while paused, waiting for the resume key
+        // The videos and the particles are frozen, only the frozen frame is shown
+        if paused {
+            window.show(&paused_frame)?;
+            match window.poll_key()? {
+                Some(KeyCommand::Pause) => {
+                    paused = false;
+                    // Don't count the pause as playback time, so the videos resume where they were
+                    previous_loop_start = std::time::Instant::now();
+                    info!("Resumed after {:.1?}", pause_start.elapsed());
+                }
+                Some(KeyCommand::Quit) => {
+                    info!("Exit");
+                    break;
+                }
+                Some(KeyCommand::Snapshot) => {
+                    let path = window.save_snapshot(
+                        &particle_system.output_frame,
+                        Path::new(&config.snapshot_folder),
+                    )?;
+                    info!("Snapshot saved: {}", path.display());
+                }
+                Some(KeyCommand::ExportSvg) => {
+                    let path = export_layout(&particle_system, Path::new(&config.snapshot_folder))?;
+                    info!("Particle layout saved: {}", path.display());
+                }
+                _ => {}
+            }
+
+            // Report the idling loop, so a pause doesn't look like a hang in the time logs
+            if last_idle_log.elapsed() >= Duration::from_secs(1) {
+                debug!(target: "time_logs", "Paused for {:.0?}, idling", pause_start.elapsed());
+                last_idle_log = std::time::Instant::now();
+            }
+
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            continue;
+        }
+
         // Measure loop start time
         let loop_start = std::time::Instant::now();
+        let elapsed_since_last_loop = loop_start - previous_loop_start;
+        previous_loop_start = loop_start;
+
+        // Apply the settings tuned with the trackbars
+        let current_interference_distance = *interference_distance.lock().unwrap();
+        let current_pixel_size = *pixel_size.lock().unwrap();
+        frame_processor.set_grayscale_threshold(*grayscale_threshold.lock().unwrap() as f64);
+        frame_processor.set_pixel_size(current_pixel_size);
+        particle_system.set_pixel_size(current_pixel_size);
+        // The same trackbar tunes the collision threshold and the squared push radius
+        particle_system.set_interference_distance(
+            (current_interference_distance * PUSH_AREA_PER_DISTANCE) as f64,
+        );
+        interference_distances.set_default(current_interference_distance);
+        // Only moved trackbars change the weights, so the configured weights stay exact
+        for (effect_type, shared_weight, applied_position) in &mut effect_weight_trackbars {
+            let position = *shared_weight.lock().unwrap();
+            if position != *applied_position {
+                effect_selector.set_weight(*effect_type, position as f64 / EFFECT_WEIGHT_STEPS);
+                *applied_position = position;
+            }
+        }
 
-        // Update the first video source frame
-        if !video_source_1.update_frame()? {
-            video_source_1.set_source_file(&args[2])?;
-            video_source_1.update_frame()?;
+        // Update the live video source frames
+        let mut input_ended = false;
+        for (index, video_source) in video_sources[..clip_layer].iter_mut().enumerate() {
+            if video_source.should_advance(elapsed_since_last_loop) {
+                live_sources_delivering[index] = true;
+                let read = match video_source.update_frame().await {
+                    // Keep the last frame of a webcam that stopped delivering, it may come back
+                    Err(VideoError::EmptyFrame(source)) => {
+                        warn!("No frame from {}, keeping the last one", source);
+                        empty_frames += 1;
+                        live_sources_delivering[index] = false;
+                        true
+                    }
+                    // Skip the frame of a hanging camera instead of freezing, it is reopened
+                    Err(VideoError::Stalled(_)) => {
+                        live_sources_delivering[index] = false;
+                        true
+                    }
+                    result => result?,
+                };
+                input_ended |= !read;
+            }
+        }
+
+        // Without a window there is no quit key, so stop when the input video ends
+        if headless && input_ended {
+            info!("Input video ended");
+            break;
         }
 
-        // Update the second video source frame
-        if !particle_system.get_animation_status(1)? {
-            if !video_source_2.update_frame()? || start_next_video {
+        // Update the clip video source frame
+        if !particle_system.get_animation_status(clip_layer)? {
+            let clip_source = &mut video_sources[clip_layer];
+            let advance = clip_source.should_advance(elapsed_since_last_loop);
+            if start_next_video || (advance && !clip_source.update_frame().await?) {
                 start_next_video = false; // Reset the flag
 
-                // If the current video ended, move to the next one
-                current_video_index = (current_video_index + 1) % video_files.len();
-                video_source_2.set_source_file(
-                    &video_files[current_video_index]
-                        .to_str()
-                        .unwrap()
-                        .to_string(),
-                )?;
-                if !video_source_2.update_frame()? {
-                    // If the new video also fails (shouldn't happen), just continue
-                    continue;
+                // Rescan the folder, since clips can be removed or added while running
+                video_files =
+                    scan_video_folder(video_folder, &config.video_extensions).unwrap_or_default();
+
+                // If the current video ended, move to the next one that plays
+                let next_video = match next_video_index(current_video_index, video_files.len()) {
+                    Some(index) => open_next_video(clip_source, &video_files, index).await,
+                    None => None,
+                };
+                match next_video {
+                    Some(index) => {
+                        current_video_index = index;
+                        waiting_for_sources = false;
+                    }
+                    None => {
+                        // No sources left, hold the last frame until files reappear
+                        if !waiting_for_sources {
+                            warn!(
+                                "No video files left in the folder: {}, holding the last frame",
+                                video_folder.display()
+                            );
+                        }
+                        waiting_for_sources = true;
+                    }
                 }
             }
         }
@@ -199,22 +1006,40 @@ async fn main() -> Result<()> {
         particle_system.clean_output_frame()?;
 
         // Access frames without cloning
-        let frame1 = {
-            let locked_frame = video_source_1.frame.lock().unwrap();
-            Arc::clone(&Arc::new(locked_frame.clone()))
-        };
-        let frame2 = {
-            let locked_frame = video_source_2.frame.lock().unwrap();
-            Arc::clone(&Arc::new(locked_frame.clone()))
-        };
+        let frames: Vec<Arc<Mat>> = video_sources
+            .iter()
+            .map(|video_source| video_source.current_frame())
+            .collect();
 
-        // Convert the frames to grayscale (black and white)
-        frame_processor.convert_to_grayscale(&frame1, 0)?;
-        frame_processor.convert_to_grayscale(&frame2, 1)?;
+        for (index, frame) in frames.iter().enumerate() {
+            match config.color_key_range {
+                // Extract the objects within the color range
+                Some((lower, upper)) => frame_processor.extract_by_color(
+                    frame,
+                    index,
+                    Scalar::new(lower[0], lower[1], lower[2], 0.0),
+                    Scalar::new(upper[0], upper[1], upper[2], 0.0),
+                )?,
+                // Extract the moving objects
+                None if frame_processor.uses_background_subtractor() => {
+                    frame_processor.convert_with_bg_subtraction(frame, index)?
+                }
+                // Convert the frame to grayscale (black and white)
+                None => frame_processor.convert_to_grayscale(frame, index)?,
+            }
 
-        // Find the contours of the objects in the frames
-        frame_processor.find_object_contour(0)?;
-        frame_processor.find_object_contour(1)?;
+            // Remove the noise of the mask
+            if config.denoise_kernel_size > 0 {
+                frame_processor.denoise_mask(index, config.denoise_kernel_size)?;
+            }
+
+            // Find the contour of the object in the frame
+            if config.track_all_contours {
+                frame_processor.find_object_contours(index, config.min_contour_area)?;
+            } else {
+                frame_processor.find_object_contour(index)?;
+            }
+        }
 
         // Measure frame processing time
         let frame_processing_time = std::time::Instant::now() - loop_start;
@@ -222,78 +1047,146 @@ async fn main() -> Result<()> {
         // Draw the contours of the objects in the frames
         // frame_processor.draw_contours(&mut particle_system.output_frame)?;
 
-        (point_1, point_2) = frame_processor.find_closest_points(0, 1).await?;
+        // Find the closest points between every pair of objects
+        let mut closest_points = Vec::new();
+        for index_1 in 0..layers_amount {
+            for index_2 in index_1 + 1..layers_amount {
+                let points = frame_processor
+                    .find_closest_points(
+                        index_1,
+                        index_2,
+                        interference_distances.get(index_1, index_2) as f64,
+                    )
+                    .await?;
+
+                // Smooth the points, so the interference line and effect anchor don't jitter
+                // Without a detection, the smoothing starts over once the objects are back
+                let points = match (points, smoothed_points.get(&(index_1, index_2))) {
+                    (Some((point_1, point_2)), Some(&(previous_1, previous_2))) => Some((
+                        smooth_point(previous_1, point_1, config.closest_points_smoothing),
+                        smooth_point(previous_2, point_2, config.closest_points_smoothing),
+                    )),
+                    (points, _) => points,
+                };
+                match points {
+                    Some(points) => smoothed_points.insert((index_1, index_2), points),
+                    None => smoothed_points.remove(&(index_1, index_2)),
+                };
+                closest_points.push((index_1, index_2, points));
+            }
+        }
 
         // Measure the closest points calculation time
         let closest_points_time = std::time::Instant::now() - loop_start - frame_processing_time;
 
-        let mut extract_object_time = std::time::Duration::new(0, 0);
-        let mut add_object_time = std::time::Duration::new(0, 0);
+        // Update the particle system around the point of the object that triggered the effect
+        let animating = particle_system.is_animating();
+        if animating {
+            let centroid = if config.effect_at_centroid {
+                frame_processor.object_centroid(clip_layer)?
+            } else {
+                None
+            };
+            let effect_point = centroid.unwrap_or_else(|| {
+                closest_points
+                    .iter()
+                    .find(|&&(index_1, index_2, _)| (index_1, index_2) == interfering_pair)
+                    .and_then(|&(_, _, points)| points)
+                    .map(|(point_1, point_2)| midpoint(point_1, point_2))
+                    .unwrap_or(Point::new(0, 0))
+            });
+            particle_system
+                .update_with_intensity(effect_point, effect_intensity)
+                .await?;
+        }
 
-        // Update the particle system
-        if particle_system.get_animation_status(1)? {
-            particle_system.update(point_1).await?;
-            object_1 = frame_processor.extract_object(0).await?;
+        // Extract the objects from the frames (black pixels) of the layers at rest
+        for (index, object) in objects.iter_mut().enumerate() {
+            if !particle_system.get_animation_status(index)? {
+                *object = frame_processor.extract_object(index).await?;
+            }
+        }
 
-            // Add the object to the particle system
-            extract_object_time = std::time::Instant::now()
-                - loop_start
-                - frame_processing_time
-                - closest_points_time;
+        // Measure the extract object time
+        let extract_object_time =
+            std::time::Instant::now() - loop_start - frame_processing_time - closest_points_time;
 
-            particle_system
-                .add_object(Arc::clone(&frame1), &object_1, 0)
-                .await?;
+        // Add the objects to the particle system
+        for (index, object) in objects.iter().enumerate() {
+            if !particle_system.get_animation_status(index)? {
+                particle_system
+                    .add_object(Arc::clone(&frames[index]), object, index)
+                    .await?;
+            }
+        }
 
-            // Measure the add object time
-            add_object_time = std::time::Instant::now()
-                - loop_start
-                - frame_processing_time
-                - closest_points_time
-                - extract_object_time;
-        } else {
-            // Extract the objects from the frames (black pixels)
-            object_1 = frame_processor.extract_object(0).await?;
-            object_2 = frame_processor.extract_object(1).await?;
+        // Measure the add object time
+        let add_object_time = std::time::Instant::now()
+            - loop_start
+            - frame_processing_time
+            - closest_points_time
+            - extract_object_time;
 
-            // Measure the extract object time
-            extract_object_time = std::time::Instant::now()
-                - loop_start
-                - frame_processing_time
-                - closest_points_time;
+        // Push the particles at rest around the mouse cursor
+        let mouse_point = *mouse_coords.lock().unwrap();
+        if !animating && mouse_point.x >= 0 && mouse_point.y >= 0 {
+            particle_system.push_at(mouse_point);
+        }
 
-            // Add the objects to the particle system
-            particle_system
-                .add_object(Arc::clone(&frame1), &object_1, 0)
-                .await?;
-            particle_system
-                .add_object(Arc::clone(&frame2), &object_2, 1)
-                .await?;
+        if !animating {
+            let interference_style = InterferenceStyle {
+                arc: config.interference_arc,
+                jaggedness: config.interference_arc_jaggedness,
+                subdivisions: config.interference_arc_subdivisions,
+                seed: frame_index,
+            };
 
-            // Measure the add object time
-            add_object_time = std::time::Instant::now()
-                - loop_start
-                - frame_processing_time
-                - closest_points_time
-                - extract_object_time;
-
-            if detect_interference(point_1, point_2, &mut particle_system.output_frame, false)? {
-                particle_system.set_animation_status(1, true);
-                let rundom_number = Rng::gen_range(&mut rand::thread_rng(), 0..3);
-                let effect = match rundom_number {
-                    0 => EffectType::Explosion,
-                    2 => EffectType::Break,
-                    _ => EffectType::Explosion,
-                };
-                particle_system.set_effect_type(1, effect);
+            // Check every pair of objects, only the layers that opt in react to the interference
+            for &(index_1, index_2, points) in &closest_points {
+                let interference = detect_interference(
+                    points,
+                    interference_distances.get(index_1, index_2),
+                    &mut particle_system.output_frame,
+                    config.draw_interference,
+                    &interference_style,
+                )?;
+                if !interference.triggered {
+                    continue;
+                }
+                let mut reacting_layers = Vec::new();
+                for index in [index_1, index_2] {
+                    if particle_system.reacts_to_interference(index)? {
+                        reacting_layers.push(index);
+                    }
+                }
+                if reacting_layers.is_empty() {
+                    continue;
+                }
 
-                // Start the next video after the interference effect
-                start_next_video = true;
+                interfering_pair = (index_1, index_2);
 
-                // Print the interference message
-                if args.len() > 4 && args[4] == "print_info" {
-                    println!("Interference detected! Effect: {:?}", effect);
+                // The closer the objects, the bigger the blast
+                let interference_distance = interference_distances.get(index_1, index_2) as f64;
+                effect_intensity = (1.0 - interference.distance / interference_distance.max(1.0))
+                    .clamp(config.min_explosion_intensity, 1.0);
+                let effect = effect_selector.pick(&mut effect_rng);
+                for &index in &reacting_layers {
+                    particle_system.set_animation_status(index, true)?;
+                    particle_system.set_effect_type(index, effect)?;
                 }
+
+                // Start the next video after the interference effect on the clip
+                if reacting_layers.contains(&clip_layer) {
+                    start_next_video = true;
+                }
+
+                // Log the interference message
+                debug!(
+                    target: "interference",
+                    "Interference detected between layers {} and {} at {:?} (distance {:.1})! Effect: {:?}",
+                    index_1, index_2, interference.midpoint, interference.distance, effect
+                );
+                break;
             }
         }
 
@@ -303,41 +1196,189 @@ async fn main() -> Result<()> {
 
         // Show the output frame in the window
         particle_system.draw()?;
+        if cli.show_stats {
+            particle_system.draw_stats()?;
+        }
+        if show_hud {
+            // The timings of the previous loop, since the current one isn't finished yet
+            window.draw_overlay(&mut particle_system.output_frame, &hud_lines)?;
+        }
         window.show(&particle_system.output_frame)?;
 
-        // Exit on 'q' key
-        if wait_key(1)? == 113 {
-            println!("Exit");
-            break;
+        // Record the output frame
+        if let Some(video_writer) = video_writer.as_mut() {
+            video_writer.write(&particle_system.output_frame)?;
+        }
+
+        // Handle the keyboard commands
+        match window.poll_key()? {
+            Some(KeyCommand::Quit) => {
+                info!("Exit");
+                break;
+            }
+            Some(KeyCommand::Pause) => {
+                paused = true;
+                pause_start = std::time::Instant::now();
+                last_idle_log = pause_start;
+
+                // Mark the shown frame as paused, the output frame itself stays clean for snapshots
+                paused_frame = particle_system.output_frame.clone();
+                window.draw_overlay(&mut paused_frame, &["Paused".to_string()])?;
+                info!("Paused");
+            }
+            Some(KeyCommand::NextVideo) => start_next_video = true,
+            Some(KeyCommand::SetEffect(effect)) => {
+                effect_intensity = 1.0;
+                particle_system.set_effect_type(clip_layer, effect)?;
+                particle_system.set_animation_status(clip_layer, true)?;
+                start_next_video = true;
+            }
+            Some(KeyCommand::Reset) => particle_system.reset(),
+            Some(KeyCommand::Snapshot) => {
+                let path = window.save_snapshot(
+                    &particle_system.output_frame,
+                    Path::new(&config.snapshot_folder),
+                )?;
+                info!("Snapshot saved: {}", path.display());
+            }
+            Some(KeyCommand::ToggleHud) => show_hud = !show_hud,
+            Some(KeyCommand::DumpState) => {
+                let path = save_state(
+                    &particle_system.dump_state(),
+                    Path::new(&config.state_folder),
+                )?;
+                info!("Particle state saved: {}", path.display());
+            }
+            Some(KeyCommand::ExportSvg) => {
+                let path = export_layout(&particle_system, Path::new(&config.snapshot_folder))?;
+                info!("Particle layout saved: {}", path.display());
+            }
+            None => {}
         }
 
         // Measure the total loop time
         let loop_time = std::time::Instant::now() - loop_start;
 
-        // Print the time logs
-        if args.len() > 4 && args[4] == "print_time_logs" {
-            println!(
-                "{:<25} {:<25} {:<25} {:<25} {:<25} {:<25}",
-                "Frame processing time:",
-                "Closest points time:",
-                "Extract object time:",
-                "Add object time:",
-                "P-system update time:",
-                "Loop time:"
-            );
-            println!(
-                "{:<25?} {:<25?} {:<25?} {:<25?} {:<25?} {:<25?}",
-                frame_processing_time,
-                closest_points_time,
-                extract_object_time,
-                add_object_time,
-                particle_system_update_time,
-                loop_time
-            );
+        // Log the time logs
+        debug!(
+            target: "time_logs",
+            "{:<25} {:<25} {:<25} {:<25} {:<25} {:<25}",
+            "Frame processing time:",
+            "Closest points time:",
+            "Extract object time:",
+            "Add object time:",
+            "P-system update time:",
+            "Loop time:"
+        );
+        debug!(
+            target: "time_logs",
+            "{:<25?} {:<25?} {:<25?} {:<25?} {:<25?} {:<25?}",
+            frame_processing_time,
+            closest_points_time,
+            extract_object_time,
+            add_object_time,
+            particle_system_update_time,
+            loop_time
+        );
+
+        // Keep the time logs for the on-screen overlay
+        if show_hud {
+            hud_lines = vec![
+                format!("Frame processing: {:.1?}", frame_processing_time),
+                format!("Closest points: {:.1?}", closest_points_time),
+                format!("Extract object: {:.1?}", extract_object_time),
+                format!("Add object: {:.1?}", add_object_time),
+                format!("P-system update: {:.1?}", particle_system_update_time),
+                format!(
+                    "Loop: {:.1?} ({:.0} FPS)",
+                    loop_time,
+                    1.0 / loop_time.as_secs_f64().max(f64::EPSILON)
+                ),
+            ];
+        }
+
+        // Count the frame only once it was processed and timed, so the summary averages
+        // over the loops that actually ran
+        frame_index += 1;
+        total_loop_time += loop_time;
+
+        // Publish the metrics of this loop
+        {
+            let animating_layer = (0..layers_amount)
+                .find(|&index| particle_system.get_animation_status(index).unwrap_or(false));
+            let mut metrics = metrics.lock().unwrap();
+            metrics.frames = frame_index;
+            metrics.loop_time = loop_time;
+            metrics.particles = particle_system.particle_count();
+            metrics.effect = animating_layer.map(|index| particle_system.effect_types()[index]);
+            metrics.empty_frames = empty_frames;
+            metrics.live_sources_delivering = live_sources_delivering.iter().all(|&ok| ok);
+        }
+
+        // Stop after the requested amount of frames
+        if cli
+            .max_frames
+            .map_or(false, |max_frames| frame_index >= max_frames)
+        {
+            info!("Reached the frame limit");
+            break;
         }
 
         // Sleep asynchronously to avoid high CPU usage
         tokio::time::sleep(Duration::from_millis(1)).await;
     }
+
+    // Log a short summary of the run
+    if frame_index > 0 {
+        info!(
+            "Processed {} frames, average loop time: {:?}",
+            frame_index,
+            Duration::from_secs_f64(total_loop_time.as_secs_f64() / frame_index as f64)
+        );
+    }
+
+    // Finish the recorded video file
+    if let Some(video_writer) = video_writer.as_mut() {
+        video_writer.release()?;
+    }
+
+    // Release the video sources and the window
+    for video_source in video_sources.iter_mut() {
+        video_source.release()?;
+    }
+    window.close()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn electric_arc_keeps_its_endpoints() {
+        let start = Point::new(10, 20);
+        let end = Point::new(300, 120);
+        let arc = electric_arc_points(start, end, 0.15, 5, 7).to_vec();
+
+        // Every subdivision doubles the segments
+        assert_eq!(arc.len(), 33);
+        assert_eq!(arc[0], start);
+        assert_eq!(arc[arc.len() - 1], end);
+        assert_eq!(arc, electric_arc_points(start, end, 0.15, 5, 7).to_vec());
+    }
+
+    #[test]
+    fn no_next_video_without_files() {
+        assert_eq!(next_video_index(0, 0), None);
+        assert_eq!(next_video_index(3, 0), None);
+    }
+
+    #[test]
+    fn next_video_wraps_around() {
+        assert_eq!(next_video_index(0, 3), Some(1));
+        assert_eq!(next_video_index(2, 3), Some(0));
+        assert_eq!(next_video_index(0, 1), Some(0));
+        // A stale index after files were removed still lands in the folder
+        assert_eq!(next_video_index(5, 2), Some(0));
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::Result; // Automatically handle the error types
+use log::{info, warn};
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::particle_system::EffectType;
+
+// Largest request head read from a client, the endpoint only needs the request line
+const MAX_REQUEST_SIZE: usize = 4096;
+
+// Numbers of the running installation, updated by the main loop every iteration
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    pub frames: u64,                   // Processed frames since the start
+    pub loop_time: Duration,           // Duration of the last loop
+    pub particles: usize,              // Particles of all layers
+    pub effect: Option<EffectType>,    // Effect being played, None at rest
+    pub empty_frames: u64,             // Frames a live source failed to deliver
+    pub live_sources_delivering: bool, // All live sources delivered their last frame
+}
+
+impl Metrics {
+    fn fps(&self) -> f64 {
+        if self.loop_time.is_zero() {
+            return 0.0;
+        }
+        1.0 / self.loop_time.as_secs_f64()
+    }
+
+    // Describe the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            let _ = writeln!(text, "{} {}", name, value);
+        };
+
+        metric(
+            "echomotion_frames_total",
+            "counter",
+            "Processed frames since the start.",
+            self.frames.to_string(),
+        );
+        metric(
+            "echomotion_loop_seconds",
+            "gauge",
+            "Duration of the last loop.",
+            format!("{:.6}", self.loop_time.as_secs_f64()),
+        );
+        metric(
+            "echomotion_fps",
+            "gauge",
+            "Frames per second of the last loop.",
+            format!("{:.2}", self.fps()),
+        );
+        metric(
+            "echomotion_particles",
+            "gauge",
+            "Particles of all layers.",
+            self.particles.to_string(),
+        );
+        metric(
+            "echomotion_empty_frames_total",
+            "counter",
+            "Frames a live source failed to deliver.",
+            self.empty_frames.to_string(),
+        );
+        metric(
+            "echomotion_live_sources_up",
+            "gauge",
+            "1 if all live sources delivered their last frame, e.g. to alert on a dropped camera.",
+            (self.live_sources_delivering as u8).to_string(),
+        );
+
+        // One series per effect, so the current one can be graphed as a state
+        let _ = writeln!(
+            text,
+            "# HELP echomotion_effect 1 for the effect being played."
+        );
+        let _ = writeln!(text, "# TYPE echomotion_effect gauge");
+        if let Some(effect) = self.effect {
+            let _ = writeln!(text, "echomotion_effect{{effect=\"{:?}\"}} 1", effect);
+        }
+        text
+    }
+}
+
+// Answer a single HTTP request, with the metrics for GET /metrics and 404 otherwise
+async fn handle_connection(mut stream: TcpStream, metrics: Arc<Mutex<Metrics>>) -> Result<()> {
+    let mut request = vec![0; MAX_REQUEST_SIZE];
+    let read = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let request_line = request.lines().next().unwrap_or_default();
+
+    let (status, body) = if request_line.starts_with("GET /metrics ") {
+        // Render under the lock, but don't hold it while writing to the client
+        let body = metrics.lock().unwrap().render();
+        ("200 OK", body)
+    } else {
+        (
+            "404 Not Found",
+            String::from("Not found, the metrics are at /metrics\n"),
+        )
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Serve the metrics at http://<host>:<port>/metrics in the background
+// Binding happens before returning, so a taken port is reported at startup
+pub async fn start_metrics_server(port: u16, metrics: Arc<Mutex<Metrics>>) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    info!("Serving the metrics on port {}", port);
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, address) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!("Unable to accept a metrics connection: {}", e);
+                    continue;
+                }
+            };
+            let metrics = Arc::clone(&metrics);
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, metrics).await {
+                    warn!("Unable to answer the metrics request of {}: {}", address, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_lists_every_metric() {
+        let metrics = Metrics {
+            frames: 42,
+            loop_time: Duration::from_millis(20),
+            particles: 1200,
+            effect: Some(EffectType::Explosion),
+            empty_frames: 3,
+            live_sources_delivering: true,
+        };
+
+        let text = metrics.render();
+
+        assert!(text.contains("echomotion_frames_total 42\n"), "{}", text);
+        assert!(text.contains("echomotion_fps 50.00\n"), "{}", text);
+        assert!(text.contains("echomotion_particles 1200\n"), "{}", text);
+        assert!(
+            text.contains("echomotion_empty_frames_total 3\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("echomotion_live_sources_up 1\n"), "{}", text);
+        assert!(
+            text.contains("echomotion_effect{effect=\"Explosion\"} 1\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn render_at_rest_has_no_effect_series() {
+        let text = Metrics::default().render();
+
+        assert!(text.contains("echomotion_fps 0.00\n"), "{}", text);
+        assert!(!text.contains("echomotion_effect{"), "{}", text);
+    }
+}
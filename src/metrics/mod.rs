@@ -0,0 +1,4 @@
+pub mod metrics;
+
+pub use metrics::start_metrics_server;
+pub use metrics::Metrics;
@@ -0,0 +1,93 @@
+use rand::Rng;
+
+use super::particle_system::EffectType;
+
+// Weighted random selection of the effect to run on interference
+pub struct EffectSelector {
+    weights: Vec<(EffectType, f64)>,
+}
+
+impl EffectSelector {
+    pub fn new(weights: &[(EffectType, f64)]) -> Self {
+        let mut selector = Self {
+            weights: Vec::with_capacity(weights.len()),
+        };
+        for &(effect_type, weight) in weights {
+            selector.set_weight(effect_type, weight);
+        }
+        selector
+    }
+
+    // Set the weight of an effect, adding it if missing (negative weights count as zero)
+    pub fn set_weight(&mut self, effect_type: EffectType, weight: f64) {
+        let weight = weight.max(0.0);
+        match self.weights.iter_mut().find(|(e, _)| *e == effect_type) {
+            Some(entry) => entry.1 = weight,
+            None => self.weights.push((effect_type, weight)),
+        }
+    }
+
+    pub fn weights(&self) -> &[(EffectType, f64)] {
+        &self.weights
+    }
+
+    // Pick an effect with a probability proportional to its weight
+    pub fn pick<R: Rng>(&self, rng: &mut R) -> EffectType {
+        let total: f64 = self.weights.iter().map(|(_, weight)| weight).sum();
+        if total <= 0.0 {
+            return EffectType::Explosion;
+        }
+
+        let mut target = rng.gen_range(0.0..total);
+        for &(effect_type, weight) in &self.weights {
+            if target < weight {
+                return effect_type;
+            }
+            target -= weight;
+        }
+
+        // Floating point rounding can leave a tiny remainder, fall back to the last effect
+        self.weights
+            .iter()
+            .rev()
+            .find(|(_, weight)| *weight > 0.0)
+            .map(|&(effect_type, _)| effect_type)
+            .unwrap_or(EffectType::Explosion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn zero_weight_effects_are_never_picked() {
+        let selector = EffectSelector::new(&[
+            (EffectType::Explosion, 0.0),
+            (EffectType::Break, 1.0),
+            (EffectType::Swirl, 0.0),
+        ]);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            assert_eq!(selector.pick(&mut rng), EffectType::Break);
+        }
+    }
+
+    #[test]
+    fn every_weighted_effect_is_reachable() {
+        let mut selector =
+            EffectSelector::new(&[(EffectType::Explosion, 2.0), (EffectType::Break, 1.0)]);
+        let mut rng = StdRng::seed_from_u64(0);
+        let picks: Vec<EffectType> = (0..1000).map(|_| selector.pick(&mut rng)).collect();
+        let breaks = picks
+            .iter()
+            .filter(|&&effect| effect == EffectType::Break)
+            .count();
+        assert!(breaks > 200 && breaks < 500);
+
+        // Changing a weight at runtime changes the mix
+        selector.set_weight(EffectType::Explosion, 0.0);
+        assert!((0..100).all(|_| selector.pick(&mut rng) == EffectType::Break));
+    }
+}
@@ -1,4 +1,11 @@
+pub mod effect_selector;
 pub mod particle_system;
 
+pub use effect_selector::EffectSelector;
+pub use particle_system::Background;
+pub use particle_system::BoundaryMode;
 pub use particle_system::EffectType;
+pub use particle_system::ParticleShape;
+pub use particle_system::ParticleStats;
 pub use particle_system::ParticleSystem;
+pub use particle_system::ParticleSystemBuilder;
@@ -1,32 +1,150 @@
 use rayon::prelude::*;
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    fs,
+    path::Path,
+    sync::Arc,
+};
 
-use anyhow::Result; // Automatically handle the error types
+use anyhow::{anyhow, bail, ensure, Result}; // Automatically handle the error types
 use opencv::{
     core::{self, Point, Rect, Scalar, Size},
     imgproc,
     prelude::*,
 };
 
-use rand::Rng;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
 
-#[derive(Clone, Copy, Debug)]
+use crate::frame_processing::PointGrid;
+use serde_json::{json, Value};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
 // Enum to represent different effects
 pub enum EffectType {
-    Push,      // Existing push-around-mouse effect
-    Break,     // Particles fall down
-    Explosion, // Particles explode away from a point
+    Push,        // Existing push-around-mouse effect
+    Break,       // Particles fall down
+    Explosion,   // Particles explode away from a point
+    Swirl,       // Particles orbit around a point
+    GravityWell, // Particles are attracted to a point
+    Implosion,   // Particles collapse into a point, then burst back out
+    Fireworks,   // Particles burst away from a point, then arc and fall down
+    Dissolve,    // Particles drift upwards and fade out, without coming back
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// Enum to represent the phase of a particle in the implosion effect
+enum ImplosionPhase {
+    Inward,  // Sucked towards the center
+    Outward, // Bursting away after the collapse
+}
+
+// Distance to the center at which an imploding particle bursts back out
+const IMPLOSION_COLLAPSE_DISTANCE: f64 = 10.0;
+
+// Particles with a lower alpha are invisible and not drawn at all
+const MIN_VISIBLE_ALPHA: f64 = 0.02;
+
+// Default speed at which particles move back to their origin
+const DEFAULT_REASSEMBLY_SPEED: f64 = 0.05;
+
+// Default amount of frames the particles that left their object take to fade out
+const DEFAULT_LEAVE_FRAMES: u32 = 6;
+
+#[derive(Clone)]
+// Enum to represent what the particles are drawn on
+pub enum Background {
+    Color(Scalar), // Plain color fill
+    Image(Mat),    // Image backdrop, resized to the window
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Color(Scalar::all(255.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+// Enum to represent how the particles are drawn
+pub enum ParticleShape {
+    #[default]
+    Rect, // Filled square, for the pixelated look
+    Circle, // Filled circle inscribed in the square
+}
+
+// Default downward acceleration and floor of the break effect
+const DEFAULT_GRAVITY: f64 = 0.5;
+const DEFAULT_FLOOR_OFFSET: i32 = 20;
+
+// Fraction of the velocity kept when a particle bounces off a window edge
+const BOUNCE_RESTITUTION: f64 = 0.6;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+// Enum to represent what happens to particles reaching the window edges
+pub enum BoundaryMode {
+    #[default]
+    Clamp, // Particles stop at the edge
+    Bounce, // Particles ricochet off the edge, losing some energy
+    Wrap,   // Particles re-enter from the opposite edge
+}
+
+#[derive(Clone, Copy, Debug)]
+// Settings shared by all particles when updating them with an effect
+struct EffectSettings {
+    interference_distance: f64,  // Squared radius of the push effect (px²)
+    swirl_clockwise: bool,       // Rotation direction of the swirl effect
+    reassembly_speed: f64,       // Fraction of the way back to the origin per update
+    explosion_intensity: f64,    // Scale of the explosion force (1.0 for a full blast)
+    boundary_mode: BoundaryMode, // Behavior of the particles at the window edges
+    hot_color: Scalar,           // Color fast explosion particles are tinted towards
+    gravity: f64,                // Downward acceleration of the break effect
+    floor_offset: i32,           // Distance of the break floor above the bottom edge
+    floor_restitution: f64,      // Fraction of the speed kept when bouncing off the floor
+    spark_color: Scalar,         // Color fireworks particles fade towards
+    wind: (f64, f64),            // Force added to the velocity of flying debris per update
+    use_mass: bool,              // Scale the break effect by the particle masses
+    fade_target: Option<Scalar>, // Color the particles fade towards, None to fade their opacity
+}
+
+impl Default for EffectSettings {
+    fn default() -> Self {
+        Self {
+            interference_distance: 10000.0,
+            swirl_clockwise: true,
+            reassembly_speed: DEFAULT_REASSEMBLY_SPEED,
+            explosion_intensity: 1.0,
+            boundary_mode: BoundaryMode::default(),
+            hot_color: Scalar::new(0.0, 100.0, 255.0, 0.0), // Orange (BGR)
+            gravity: DEFAULT_GRAVITY,
+            floor_offset: DEFAULT_FLOOR_OFFSET,
+            floor_restitution: 0.0,
+            spark_color: Scalar::new(180.0, 240.0, 255.0, 0.0), // Warm white (BGR)
+            wind: (0.0, 0.0),
+            use_mass: false,
+            fade_target: Some(Scalar::all(255.0)), // Matches the default white background
+        }
+    }
 }
 
 struct Particle {
     window_size: Size,
     origin: Point,
     size: i32,
+    initial_color: core::Scalar,
     color: core::Scalar,
+    tint: core::Scalar, // Part of the color added by the explosion heat, on top of the fading
+    alpha: f64,
     x: f64,
     y: f64,
     vx: f64,
     vy: f64,
+    swirl_energy: f64,
+    implosion_phase: ImplosionPhase,
+    launched: bool,
+    age: u32,
+    mass: f64,
+    leaving: Option<u32>, // Frames left to fade out, once the origin left the object
     pub on_position: bool,
 }
 
@@ -36,42 +154,166 @@ impl Particle {
             window_size,
             origin,
             size,
+            initial_color: color,
             color,
+            tint: Scalar::default(),
+            alpha: 1.0,
             x: origin.x as f64,
             y: origin.y as f64,
             vx: 0.0,
             vy: 0.0,
+            swirl_energy: 1.0,
+            implosion_phase: ImplosionPhase::Inward,
+            launched: false,
+            age: 0,
+            mass: Self::mass_of(color),
+            leaving: None,
             on_position: false,
         }
     }
 
+    // Mass from 0.5 for white to 1.5 for black, so darker particles feel heavier
+    fn mass_of(color: Scalar) -> f64 {
+        let brightness = (color[0] + color[1] + color[2]) / (3.0 * 255.0);
+        1.5 - brightness.clamp(0.0, 1.0)
+    }
+
+    // Describe the particle state as JSON, for debugging
+    pub fn dump_state(&self) -> Value {
+        json!({
+            "origin": [self.origin.x, self.origin.y],
+            "position": [self.x, self.y],
+            "velocity": [self.vx, self.vy],
+            "color": [self.color[0], self.color[1], self.color[2]],
+            "alpha": self.alpha,
+            "size": self.size,
+            "on_position": self.on_position,
+        })
+    }
+
+    // Put the particle back at its origin, at rest and with its initial color
+    pub fn reset(&mut self) {
+        self.x = self.origin.x as f64;
+        self.y = self.origin.y as f64;
+        self.vx = 0.0;
+        self.vy = 0.0;
+        self.color = self.initial_color;
+        self.tint = Scalar::default();
+        self.alpha = 1.0;
+        self.swirl_energy = 1.0;
+        self.implosion_phase = ImplosionPhase::Inward;
+        self.launched = false;
+        self.on_position = true;
+    }
+
     pub fn update_with_effect(
         &mut self,
         effect_type: &EffectType,
         mouse_coords: Point,
-        interference_distance: f64,
+        settings: &EffectSettings,
+        rng: &mut StdRng,
     ) {
         match effect_type {
-            EffectType::Push => self.update_push(mouse_coords, interference_distance),
-            EffectType::Break => self.update_break(),
-            EffectType::Explosion => self.update_explosion(mouse_coords),
+            EffectType::Push => self.update_push(
+                mouse_coords,
+                settings.interference_distance,
+                settings.reassembly_speed,
+                settings.boundary_mode,
+            ),
+            EffectType::Break => self.update_break(
+                settings.gravity,
+                settings.floor_offset,
+                settings.floor_restitution,
+                settings.wind,
+                if settings.use_mass { self.mass } else { 1.0 },
+                rng,
+            ),
+            EffectType::Explosion => self.update_explosion(
+                mouse_coords,
+                settings.explosion_intensity,
+                settings.hot_color,
+                settings.boundary_mode,
+                settings.wind,
+                rng,
+            ),
+            EffectType::Swirl => self.update_swirl(
+                mouse_coords,
+                settings.swirl_clockwise,
+                settings.reassembly_speed,
+                settings.boundary_mode,
+            ),
+            EffectType::GravityWell => {
+                self.update_gravity_well(mouse_coords, settings.boundary_mode)
+            }
+            EffectType::Implosion => self.update_implosion(
+                mouse_coords,
+                settings.hot_color,
+                settings.boundary_mode,
+                rng,
+            ),
+            EffectType::Fireworks => self.update_fireworks(
+                mouse_coords,
+                settings.gravity,
+                settings.spark_color,
+                settings.boundary_mode,
+                rng,
+            ),
+            EffectType::Dissolve => self.update_dissolve(rng),
         }
 
         // Fade color
-        self.fade_color(0.98);
+        self.fade_color(0.98, settings.fade_target);
+        self.age = self.age.saturating_add(1);
     }
 
-    fn fade_color(&mut self, factor: f64) {
-        self.color = Scalar::new(
-            self.color[0] * factor,
-            self.color[1] * factor,
-            self.color[2] * factor,
-            self.color[3],
-        );
+    // Check if the particle sits exactly at its origin, as part of the object at rest
+    fn at_origin(&self) -> bool {
+        self.x == self.origin.x as f64 && self.y == self.origin.y as f64
+    }
+
+    // Center of the square of the particle
+    fn center(&self) -> (f64, f64) {
+        let half_size = self.size as f64 / 2.0;
+        (self.x + half_size, self.y + half_size)
+    }
+
+    // Opacity to draw the particle with, ramping up from 0 during its first spawn frames
+    // and down to 0 over the leave frames once it left the object
+    fn visible_alpha(&self, spawn_frames: u32, leave_frames: u32) -> f64 {
+        let mut alpha = self.alpha;
+        if spawn_frames > 0 {
+            alpha *= (self.age as f64 / spawn_frames as f64).min(1.0);
+        }
+        if let Some(remaining) = self.leaving {
+            alpha *= remaining as f64 / (leave_frames + 1) as f64;
+        }
+        alpha
+    }
+
+    // Fade the particle out by decaying its opacity, so it dissolves into the background
+    // With a target color, the color moves towards it instead, keeping the particle opaque
+    fn fade_color(&mut self, factor: f64, target: Option<Scalar>) {
+        match target {
+            Some(target) => {
+                for channel in 0..3 {
+                    self.color[channel] =
+                        target[channel] + (self.color[channel] - target[channel]) * factor;
+                    // Fade the tint along, so the color without it is the faded base color
+                    self.tint[channel] *= factor;
+                }
+            }
+            None => self.alpha *= factor,
+        }
     }
 
     // Update the particle with the push effect based on the given point
-    fn update_push(&mut self, point: Point, interference_distance: f64) {
+    fn update_push(
+        &mut self,
+        point: Point,
+        interference_distance: f64,
+        reassembly_speed: f64,
+        boundary_mode: BoundaryMode,
+    ) {
         // Influence by mouse
         let dx = point.x as f64 - self.x;
         let dy = point.y as f64 - self.y;
@@ -93,41 +335,70 @@ impl Particle {
         self.vx *= friction;
         self.vy *= friction;
 
-        self.check_world_boundaries();
-        self.move_towards_origin();
+        self.check_world_boundaries(boundary_mode);
+        self.move_towards_origin(reassembly_speed);
     }
 
-    fn update_break(&mut self) {
-        self.vy += 0.5; // Simulate gravity by incrementing vertical velocity
+    // Update the particle with the break effect, falling down onto the floor
+    fn update_break(
+        &mut self,
+        gravity: f64,
+        floor_offset: i32,
+        floor_restitution: f64,
+        wind: (f64, f64),
+        mass: f64,
+        rng: &mut StdRng,
+    ) {
+        // Simulate gravity by incrementing vertical velocity, heavier particles fall faster
+        self.vy += gravity * mass;
 
-        // Introduce slight horizontal randomness
-        let mut rng = rand::thread_rng();
+        // Introduce slight horizontal randomness, lighter particles flutter more
         let horizontal_force: f64 = rng.gen_range(-0.5..0.5);
-        self.vx += horizontal_force;
+        self.vx += horizontal_force / mass;
+
+        // Blow the falling particles, the ones lying on the floor stay put
+        let floor = (self.window_size.height - floor_offset) as f64;
+        if self.y < floor {
+            self.vx += wind.0;
+            self.vy += wind.1;
+        }
 
         // Apply damping to both velocities
         self.vx *= 0.98;
         self.vy *= 0.98;
         self.x += self.vx;
+        self.y += self.vy;
 
-        if self.y >= (self.window_size.height as f64 - 20.0) {
-            self.y = self.window_size.height as f64 - 20.0; // Stop particles at the bottom
-            self.vy = 0.0;
-            self.on_position = true;
+        if self.y >= floor {
+            self.y = floor;
+            // Bounce back up, until the bounce is too small to notice
+            if self.vy * floor_restitution >= 1.0 {
+                self.vy = -self.vy * floor_restitution;
+                self.on_position = false;
+            } else {
+                self.vy = 0.0; // Stop particles at the bottom
+                self.on_position = true;
+            }
         } else {
-            self.y += self.vy;
             self.on_position = false;
         }
     }
 
-    fn update_explosion(&mut self, explosion_center: Point) {
+    fn update_explosion(
+        &mut self,
+        explosion_center: Point,
+        intensity: f64,
+        hot_color: Scalar,
+        boundary_mode: BoundaryMode,
+        wind: (f64, f64),
+        rng: &mut StdRng,
+    ) {
         let dx = self.x - explosion_center.x as f64;
         let dy = self.y - explosion_center.y as f64;
         let distance = (dx * dx + dy * dy).sqrt().max(1.0); // Avoid division by zero
 
         // Base force and randomness
-        let base_force = 10000.0 / distance;
-        let mut rng = rand::thread_rng();
+        let base_force = 10000.0 * intensity / distance;
         let random_factor: f64 = rng.gen_range(0.8..1.2); // Random force scaling
         let random_angle: f64 = rng.gen_range(-0.1..0.1); // Random angle variation
 
@@ -138,15 +409,16 @@ impl Particle {
         self.vx += adjusted_force * angle.cos();
         self.vy += adjusted_force * angle.sin();
 
-        // Cap velocity to prevent excessive speeds
-        let max_velocity = 20.0; // Maximum velocity
-        let speed = (self.vx * self.vx + self.vy * self.vy).sqrt();
-        if speed > max_velocity {
-            let scale = max_velocity / speed;
-            self.vx *= scale;
-            self.vy *= scale;
+        // Drift the flying debris, the particles that came to rest stay put
+        if !self.on_position {
+            self.vx += wind.0;
+            self.vy += wind.1;
         }
 
+        // Cap velocity to prevent excessive speeds
+        let max_velocity = 20.0;
+        self.limit_velocity(max_velocity);
+
         // Apply damping
         self.vx *= 0.90; // Reduced damping for faster movement
         self.vy *= 0.90; // Reduced damping
@@ -155,8 +427,13 @@ impl Particle {
         self.x += self.vx;
         self.y += self.vy;
 
-        self.check_world_boundaries();
-        if (self.x >= self.window_size.width as f64 - 1.0
+        let hit_boundary = self.check_world_boundaries(boundary_mode);
+        if boundary_mode != BoundaryMode::Clamp {
+            // Keep the debris moving, the explosion is done once it reached an edge
+            if hit_boundary {
+                self.on_position = true;
+            }
+        } else if (self.x >= self.window_size.width as f64 - 1.0
             || self.x <= 1.0
             || self.y >= self.window_size.height as f64 - 1.0
             || self.y <= 1.0)
@@ -167,11 +444,219 @@ impl Particle {
         } else {
             self.on_position = false;
         }
+
+        // Tint the particle towards the hot color the faster it moves, cooling down as it slows
+        // The tint of the last frame is removed first, so the faded base color shows at rest
+        let speed = (self.vx * self.vx + self.vy * self.vy).sqrt();
+        let heat = (speed / max_velocity).min(1.0);
+        for channel in 0..3 {
+            let base = self.color[channel] - self.tint[channel];
+            self.tint[channel] = (hot_color[channel] - base) * heat;
+            self.color[channel] = base + self.tint[channel];
+        }
+    }
+
+    // Update the particle with the swirl effect, orbiting around the given center
+    fn update_swirl(
+        &mut self,
+        center: Point,
+        clockwise: bool,
+        reassembly_speed: f64,
+        boundary_mode: BoundaryMode,
+    ) {
+        let dx = self.x - center.x as f64;
+        let dy = self.y - center.y as f64;
+        let distance = (dx * dx + dy * dy).sqrt().max(1.0); // Avoid division by zero
+
+        // Tangential direction, perpendicular to the radial vector
+        let (tangent_x, tangent_y) = if clockwise {
+            (-dy / distance, dx / distance)
+        } else {
+            (dy / distance, -dx / distance)
+        };
+
+        // Orbit faster further away from the center, decaying over time
+        let tangential_force = distance * 0.05 * self.swirl_energy;
+        self.vx += tangential_force * tangent_x;
+        self.vy += tangential_force * tangent_y;
+        self.swirl_energy *= 0.98;
+
+        // Cap velocity to prevent excessive speeds
+        self.limit_velocity(20.0);
+
+        // Apply damping
+        self.vx *= 0.90;
+        self.vy *= 0.90;
+
+        // Pull back to the origin, which takes over once the swirl has decayed
+        self.check_world_boundaries(boundary_mode);
+        self.move_towards_origin(reassembly_speed);
+    }
+
+    // Update the particle with the gravity well effect, attracting it to the given center
+    fn update_gravity_well(&mut self, center: Point, boundary_mode: BoundaryMode) {
+        let dx = center.x as f64 - self.x;
+        let dy = center.y as f64 - self.y;
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        // Let the particles cluster at the center rather than oscillate around it
+        if distance < 3.0 {
+            self.vx = 0.0;
+            self.vy = 0.0;
+            self.on_position = true;
+            return;
+        }
+
+        // Force inversely proportional to the squared distance
+        let force = 50000.0 / (distance * distance).max(1.0);
+        self.vx += force * dx / distance;
+        self.vy += force * dy / distance;
+
+        // Cap velocity to prevent excessive speeds
+        self.limit_velocity(20.0);
+
+        // Apply damping
+        self.vx *= 0.90;
+        self.vy *= 0.90;
+
+        // Update positions
+        self.x += self.vx;
+        self.y += self.vy;
+
+        self.check_world_boundaries(boundary_mode);
+        self.on_position = false;
+    }
+
+    // Update the particle with the dissolve effect, drifting upwards while fading out
+    // The particle never returns, it is dropped once it became invisible
+    fn update_dissolve(&mut self, rng: &mut StdRng) {
+        self.vx += rng.gen_range(-0.3..0.3);
+        self.vy += rng.gen_range(-0.3..0.1); // Bias upwards, like rising smoke
+
+        // Apply damping
+        self.vx *= 0.95;
+        self.vy *= 0.95;
+
+        // Update positions
+        self.x += self.vx;
+        self.y += self.vy;
+
+        self.alpha *= 0.95;
+        self.on_position = false;
+    }
+
+    // Update the particle with the implosion effect, sucking it into the given center
+    // and bursting it back out once it collapsed
+    fn update_implosion(
+        &mut self,
+        center: Point,
+        hot_color: Scalar,
+        boundary_mode: BoundaryMode,
+        rng: &mut StdRng,
+    ) {
+        if self.implosion_phase == ImplosionPhase::Outward {
+            self.update_explosion(center, 1.0, hot_color, boundary_mode, (0.0, 0.0), rng);
+            return;
+        }
+
+        let dx = center.x as f64 - self.x;
+        let dy = center.y as f64 - self.y;
+        let distance = (dx * dx + dy * dy).sqrt();
+
+        if distance < IMPLOSION_COLLAPSE_DISTANCE {
+            self.implosion_phase = ImplosionPhase::Outward;
+            self.vx = 0.0;
+            self.vy = 0.0;
+            self.on_position = false;
+            return;
+        }
+
+        // Accelerate towards the center, faster the closer the particle gets
+        let force = 2000.0 / distance;
+        self.vx += force * dx / distance;
+        self.vy += force * dy / distance;
+
+        // Cap velocity to prevent excessive speeds
+        self.limit_velocity(20.0);
+
+        // Apply damping
+        self.vx *= 0.90;
+        self.vy *= 0.90;
+
+        // Update positions
+        self.x += self.vx;
+        self.y += self.vy;
+
+        self.check_world_boundaries(boundary_mode);
+        self.on_position = false;
+    }
+
+    // Update the particle with the fireworks effect, launching it away from the given center once
+    // and letting gravity pull it down afterwards
+    fn update_fireworks(
+        &mut self,
+        center: Point,
+        gravity: f64,
+        spark_color: Scalar,
+        boundary_mode: BoundaryMode,
+        rng: &mut StdRng,
+    ) {
+        if !self.launched {
+            let dx = self.x - center.x as f64;
+            let dy = self.y - center.y as f64;
+
+            // Burst outward with a random speed and a slight random direction
+            let angle = dy.atan2(dx) + rng.gen_range(-0.2..0.2);
+            let launch_speed: f64 = rng.gen_range(10.0..20.0);
+            self.vx = launch_speed * angle.cos();
+            self.vy = launch_speed * angle.sin();
+            self.launched = true;
+            self.on_position = false;
+        }
+
+        self.vy += gravity; // Simulate gravity by incrementing vertical velocity
+
+        // Cap velocity to prevent excessive speeds
+        self.limit_velocity(20.0);
+
+        // Apply damping
+        self.vx *= 0.98;
+        self.vy *= 0.98;
+
+        // Update positions
+        self.x += self.vx;
+        self.y += self.vy;
+
+        // Done once the particle reached the bottom edge, checked before it bounces or wraps
+        let landed = self.y >= self.window_size.height as f64 - 1.0;
+        self.check_world_boundaries(boundary_mode);
+        if landed {
+            if boundary_mode == BoundaryMode::Clamp {
+                self.vy = 0.0; // Rest on the bottom edge
+            }
+            self.on_position = true;
+        }
+
+        // Fade the color towards the spark color
+        for channel in 0..3 {
+            self.color[channel] += (spark_color[channel] - self.color[channel]) * 0.05;
+        }
+    }
+
+    // Scale the velocity down if the speed exceeds the maximum velocity
+    fn limit_velocity(&mut self, max_velocity: f64) {
+        let speed = (self.vx * self.vx + self.vy * self.vy).sqrt();
+        if speed > max_velocity {
+            let scale = max_velocity / speed;
+            self.vx *= scale;
+            self.vy *= scale;
+        }
     }
 
-    fn move_towards_origin(&mut self) {
-        self.x += (self.origin.x as f64 - self.x) * 0.05 + self.vx;
-        self.y += (self.origin.y as f64 - self.y) * 0.05 + self.vy;
+    // Move the particle towards its origin by the given fraction of the remaining distance
+    fn move_towards_origin(&mut self, speed: f64) {
+        self.x += (self.origin.x as f64 - self.x) * speed + self.vx;
+        self.y += (self.origin.y as f64 - self.y) * speed + self.vy;
 
         if (self.origin.x as f64 - self.x).abs() < 1.0
             && (self.origin.y as f64 - self.y).abs() < 1.0
@@ -184,20 +669,123 @@ impl Particle {
         }
     }
 
-    fn check_world_boundaries(&mut self) {
-        if self.x < 0.0 {
-            self.x = 0.0;
-        }
-        if self.y < 0.0 {
-            self.y = 0.0;
-        }
-        if self.x > self.window_size.width as f64 {
-            self.x = self.window_size.width as f64;
+    // Keep the particle inside the window, returns true if it reached an edge
+    fn check_world_boundaries(&mut self, mode: BoundaryMode) -> bool {
+        let width = self.window_size.width as f64;
+        let height = self.window_size.height as f64;
+        let hit_x = self.x < 0.0 || self.x > width;
+        let hit_y = self.y < 0.0 || self.y > height;
+
+        match mode {
+            BoundaryMode::Clamp => {
+                self.x = self.x.clamp(0.0, width);
+                self.y = self.y.clamp(0.0, height);
+            }
+            BoundaryMode::Bounce => {
+                if hit_x {
+                    self.x = self.x.clamp(0.0, width);
+                    self.vx = -self.vx * BOUNCE_RESTITUTION;
+                }
+                if hit_y {
+                    self.y = self.y.clamp(0.0, height);
+                    self.vy = -self.vy * BOUNCE_RESTITUTION;
+                }
+            }
+            BoundaryMode::Wrap => {
+                if hit_x {
+                    self.x = self.x.rem_euclid(width.max(1.0));
+                }
+                if hit_y {
+                    self.y = self.y.rem_euclid(height.max(1.0));
+                }
+            }
         }
-        if self.y > self.window_size.height as f64 {
-            self.y = self.window_size.height as f64;
+
+        hit_x || hit_y
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+// Snapshot of the particle counts, to monitor the load of the particle system
+pub struct ParticleStats {
+    pub particles: Vec<usize>,   // Amount of particles per layer
+    pub on_position: Vec<usize>, // Amount of particles at rest per layer
+}
+
+impl ParticleStats {
+    pub fn total_particles(&self) -> usize {
+        self.particles.iter().sum()
+    }
+
+    pub fn total_on_position(&self) -> usize {
+        self.on_position.iter().sum()
+    }
+}
+
+// Builder to configure a particle system with named settings
+pub struct ParticleSystemBuilder {
+    window_size: Size,
+    pixel_size: i32,
+    pixel_spacing: i32,
+    interference_distance: i32,
+}
+
+impl Default for ParticleSystemBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParticleSystemBuilder {
+    pub fn new() -> Self {
+        Self {
+            window_size: Size::default(),
+            pixel_size: 10,
+            pixel_spacing: 0,
+            interference_distance: 10000,
         }
     }
+
+    pub fn window_size(mut self, window_size: Size) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    pub fn pixel_size(mut self, pixel_size: i32) -> Self {
+        self.pixel_size = pixel_size;
+        self
+    }
+
+    pub fn pixel_spacing(mut self, pixel_spacing: i32) -> Self {
+        self.pixel_spacing = pixel_spacing;
+        self
+    }
+
+    // Set the squared radius of the push effect (px²), e.g. 10000 for a 100 pixels radius
+    pub fn interference_distance(mut self, interference_distance: i32) -> Self {
+        self.interference_distance = interference_distance;
+        self
+    }
+
+    pub fn build(self) -> Result<ParticleSystem> {
+        ensure!(
+            self.pixel_size > 0,
+            "Pixel size must be positive, got {}",
+            self.pixel_size
+        );
+        ensure!(
+            self.interference_distance >= 0,
+            "Interference distance must not be negative, got {}",
+            self.interference_distance
+        );
+
+        Ok(ParticleSystem::new(
+            self.window_size,
+            self.pixel_size,
+            self.pixel_spacing,
+            self.interference_distance,
+        ))
+    }
 }
 
 pub struct ParticleSystem {
@@ -206,12 +794,31 @@ pub struct ParticleSystem {
     pixel_size: i32,
     pixel_spacing: i32,
     animation_statuses: Vec<bool>,
-    interference_distance: f64,
+    reacts_to_interference: Vec<bool>,
+    frozen: Vec<bool>,
+    rngs: Vec<StdRng>,
+    seed: Option<u64>,
+    collision: bool,
+    effect_frames: Vec<u32>,
+    effect_max_frames: Vec<Option<u32>>,
     effect_types: Vec<EffectType>,
+    effect_settings: EffectSettings,
+    snap_to_grid: bool,
+    trail_decay: f64,
+    size_jitter: f64,
+    spawn_frames: u32,
+    leave_frames: u32,
+    particle_shape: ParticleShape,
+    antialiasing: bool,
+    background: Background,
     pub output_frame: Mat,
 }
 
 impl ParticleSystem {
+    pub fn builder() -> ParticleSystemBuilder {
+        ParticleSystemBuilder::new()
+    }
+
     pub fn new(
         window_size: Size,
         pixel_size: i32,
@@ -224,8 +831,26 @@ impl ParticleSystem {
             pixel_size,
             pixel_spacing,
             animation_statuses: Vec::new(),
-            interference_distance: interference_distance as f64,
+            reacts_to_interference: Vec::new(),
+            frozen: Vec::new(),
+            rngs: Vec::new(),
+            seed: None,
+            collision: false,
+            effect_frames: Vec::new(),
+            effect_max_frames: Vec::new(),
             effect_types: Vec::new(),
+            effect_settings: EffectSettings {
+                interference_distance: interference_distance as f64,
+                ..EffectSettings::default()
+            },
+            snap_to_grid: false,
+            trail_decay: 1.0,
+            size_jitter: 0.0,
+            spawn_frames: 0,
+            leave_frames: DEFAULT_LEAVE_FRAMES,
+            particle_shape: ParticleShape::default(),
+            antialiasing: false,
+            background: Background::default(),
             output_frame: Mat::default(),
         }
     }
@@ -233,22 +858,63 @@ impl ParticleSystem {
     pub fn init(&mut self, frame: &Mat, amount: i32) -> Result<()> {
         self.particle_system.clear();
         self.animation_statuses.clear();
+        self.reacts_to_interference.clear();
+        self.frozen.clear();
+        self.rngs.clear();
+        self.effect_frames.clear();
+        self.effect_max_frames.clear();
         self.effect_types.clear();
 
         for _ in 0..amount {
             self.particle_system.push(Vec::new());
             self.animation_statuses.push(false);
+            self.reacts_to_interference.push(true);
+            self.frozen.push(false);
+            self.rngs.push(Self::layer_rng(self.seed, self.rngs.len()));
+            self.effect_frames.push(0);
+            self.effect_max_frames.push(None);
             self.effect_types.push(EffectType::Push);
         }
 
+        // Start from a clean frame, so no video content leaks into the trails
         self.output_frame = frame.clone();
+        self.fill_background()?;
 
         Ok(())
     }
 
+    // Random numbers of a layer, reproducible with a seed and from the OS otherwise
+    // Every layer has its own stream, so the layers can be updated in parallel
+    fn layer_rng(seed: Option<u64>, index: usize) -> StdRng {
+        match seed {
+            Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(index as u64)),
+            None => StdRng::from_entropy(),
+        }
+    }
+
     // Get the color of a pixel in the frame at the given point synchronously
-    fn get_pixel_color_sync(frame: Arc<Mat>, point: &Point, _pixel_size: i32) -> Result<Scalar> {
-        let color = frame.at_2d::<core::Vec3b>(point.y, point.x)?;
+    fn get_pixel_color_sync(frame: &Mat, point: &Point) -> Result<Scalar> {
+        ensure!(
+            !frame.empty(),
+            "Unable to read a pixel color of an empty frame"
+        );
+
+        // Clamp the point to the frame, points on the edge can be off by one after resizing
+        let x = point.x.clamp(0, frame.cols() - 1);
+        let y = point.y.clamp(0, frame.rows() - 1);
+
+        let color = match frame.channels() {
+            3 => *frame.at_2d::<core::Vec3b>(y, x)?,
+            4 => {
+                let color = frame.at_2d::<core::Vec4b>(y, x)?;
+                core::Vec3b::from([color[0], color[1], color[2]])
+            }
+            1 => core::Vec3b::all(*frame.at_2d::<u8>(y, x)?),
+            channels => bail!(
+                "Unable to read a pixel color of a frame with {} channels",
+                channels
+            ),
+        };
         Ok(Scalar::new(
             color[0] as f64,
             color[1] as f64,
@@ -257,53 +923,104 @@ impl ParticleSystem {
         ))
     }
 
+    // Snap the points to the nearest cell of the pixel grid and remove the duplicates
+    fn snap_points_to_grid(points: &[Point], cell_size: i32, frame_size: Size) -> Vec<Point> {
+        let max_x = ((frame_size.width - 1).max(0) / cell_size) * cell_size;
+        let max_y = ((frame_size.height - 1).max(0) / cell_size) * cell_size;
+
+        let mut seen = HashSet::with_capacity(points.len());
+        let mut snapped = Vec::with_capacity(points.len());
+        for point in points {
+            let x =
+                ((point.x as f64 / cell_size as f64).round() as i32 * cell_size).clamp(0, max_x);
+            let y =
+                ((point.y as f64 / cell_size as f64).round() as i32 * cell_size).clamp(0, max_y);
+            if seen.insert((x, y)) {
+                snapped.push(Point::new(x, y));
+            }
+        }
+        snapped
+    }
+
     pub async fn add_object(
         &mut self,
         frame: Arc<Mat>,
         object: &Vec<Point>,
         index: usize,
     ) -> Result<()> {
-        if object.is_empty() {
-            self.particle_system[index].clear();
-            self.animation_statuses[index] = false;
-            return Ok(());
-        }
+        // Align the particle origins to the pixel grid for a clean mosaic
+        let snapped_object;
+        let object = if self.snap_to_grid {
+            snapped_object = Self::snap_points_to_grid(object, self.pixel_size, frame.size()?);
+            &snapped_object
+        } else {
+            object
+        };
 
         let window_size = self.window_size;
         let pixel_size = self.pixel_size;
-        let num_cpus = num_cpus::get();
-        let chunk_size = (object.len() + num_cpus - 1) / num_cpus;
-        let chunk_size = if chunk_size == 0 { 1 } else { chunk_size }; // Ensure chunk_size >= 1
-
-        // Use a reference-counted pointer to the frame
-        let frame_arc = Arc::clone(&frame);
-
-        let mut tasks = Vec::new();
-
-        for chunk in object.chunks(chunk_size) {
-            let frame_clone = Arc::clone(&frame_arc);
-            let chunk_data = chunk.to_vec();
-            let pixel_size = pixel_size;
-            let window_size = window_size;
-            tasks.push(tokio::task::spawn_blocking(move || {
-                let mut particles = Vec::with_capacity(chunk_data.len());
-                for point in chunk_data {
-                    let color = ParticleSystem::get_pixel_color_sync(
-                        frame_clone.clone(),
-                        &point,
-                        pixel_size,
-                    )?;
-                    particles.push(Particle::new(window_size, point, pixel_size, color));
+        let size_jitter = self.size_jitter;
+
+        // Sample the colors on rayon's thread pool, from one blocking task so the async
+        // runtime isn't held up. The colors are read clamped to the frame, so points on the
+        // edge can't read out of bounds. The particles keep the order of the points
+        let points = object.to_vec();
+        let mut all_particles = tokio::task::spawn_blocking(move || {
+            points
+                .par_iter()
+                .map(|point| {
+                    let color = ParticleSystem::get_pixel_color_sync(&frame, point)?;
+                    Ok(Particle::new(window_size, *point, pixel_size, color))
+                })
+                .collect::<Result<Vec<Particle>>>()
+        })
+        .await??;
+
+        // Vary the size around the pixel size for a more organic look
+        // Drawn in order from the layer's random numbers, so a seed gives the same sizes
+        if size_jitter > 0.0 {
+            let rng = &mut self.rngs[index];
+            for particle in all_particles.iter_mut() {
+                let scale = 1.0 + rng.gen_range(-size_jitter..=size_jitter);
+                particle.size = ((pixel_size as f64 * scale).round() as i32).max(1);
+            }
+        }
+
+        let previous_particles = std::mem::take(&mut self.particle_system[index]);
+
+        // Particles that already existed keep aging, so only the new ones fade in
+        if self.spawn_frames > 0 {
+            let ages: HashMap<(i32, i32), u32> = previous_particles
+                .iter()
+                .filter(|particle| particle.leaving.is_none())
+                .map(|particle| ((particle.origin.x, particle.origin.y), particle.age))
+                .collect();
+            for particle in all_particles.iter_mut() {
+                if let Some(age) = ages.get(&(particle.origin.x, particle.origin.y)) {
+                    particle.age = age.saturating_add(1);
                 }
-                Ok::<Vec<Particle>, anyhow::Error>(particles)
-            }));
+            }
         }
 
-        // Wait for all tasks to complete and gather results
-        let mut all_particles = Vec::with_capacity(object.len());
-        for t in tasks {
-            let mut partial = t.await??;
-            all_particles.append(&mut partial);
+        // Fade out the particles whose origin is no longer part of the object, instead of
+        // dropping them, which smooths the flicker of the shifting object points
+        if self.leave_frames > 0 {
+            let origins: HashSet<(i32, i32)> = all_particles
+                .iter()
+                .map(|particle| (particle.origin.x, particle.origin.y))
+                .collect();
+            for mut particle in previous_particles {
+                if origins.contains(&(particle.origin.x, particle.origin.y)) {
+                    continue; // Replaced by the new particle of the same origin
+                }
+                particle.leaving = match particle.leaving {
+                    Some(remaining) => remaining.checked_sub(1).filter(|&left| left > 0),
+                    None => Some(self.leave_frames),
+                };
+                if particle.leaving.is_some() {
+                    all_particles.push(particle);
+                }
+            }
         }
 
         self.particle_system[index] = all_particles;
@@ -311,79 +1028,1217 @@ impl ParticleSystem {
         Ok(())
     }
 
+    // Add the object of an image (e.g. a logo) with its top left corner at the position
+    // The transparent pixels are skipped, images without an alpha channel are fully opaque
+    pub fn add_object_from_image(
+        &mut self,
+        image: &Mat,
+        index: usize,
+        position: Point,
+    ) -> Result<()> {
+        if index >= self.particle_system.len() {
+            return Err(Self::missing_layer(index, self.particle_system.len()));
+        }
+
+        let window_size = self.window_size;
+        let pixel_size = self.pixel_size;
+        let step = (self.pixel_size + self.pixel_spacing).max(1) as usize;
+        let channels = image.channels();
+        let cols = image.cols();
+
+        // Sample the image on the pixel grid, one row of the grid per task
+        let rows: Vec<i32> = (0..image.rows()).step_by(step).collect();
+        let particles = rows
+            .par_iter()
+            .map(|&y| {
+                let mut particles = Vec::new();
+                for x in (0..cols).step_by(step) {
+                    let (color, alpha) = match channels {
+                        4 => {
+                            let pixel = image.at_2d::<core::Vec4b>(y, x)?;
+                            ([pixel[0], pixel[1], pixel[2]], pixel[3])
+                        }
+                        3 => {
+                            let pixel = image.at_2d::<core::Vec3b>(y, x)?;
+                            ([pixel[0], pixel[1], pixel[2]], 255)
+                        }
+                        _ => {
+                            let value = *image.at_2d::<u8>(y, x)?;
+                            ([value; 3], 255)
+                        }
+                    };
+
+                    // Skip the (mostly) transparent pixels and the ones outside the window
+                    let origin = Point::new(position.x + x, position.y + y);
+                    if alpha < 128
+                        || origin.x < 0
+                        || origin.y < 0
+                        || origin.x >= window_size.width
+                        || origin.y >= window_size.height
+                    {
+                        continue;
+                    }
+
+                    let color = Scalar::new(color[0] as f64, color[1] as f64, color[2] as f64, 0.0);
+                    particles.push(Particle::new(window_size, origin, pixel_size, color));
+                }
+                Ok(particles)
+            })
+            .collect::<Result<Vec<Vec<Particle>>>>()?;
+
+        self.particle_system[index] = particles.into_iter().flatten().collect();
+        self.animation_statuses[index] = false;
+        Ok(())
+    }
+
     // Update the particle system with the given point
     pub async fn update(&mut self, point: Point) -> Result<()> {
-        let effect_types = self.effect_types.clone();
-        let interference_distance = self.interference_distance;
+        self.update_with_intensity(point, 1.0).await
+    }
+
+    // Update the particle system with the given point, scaling the explosion force
+    // by the intensity (e.g. 0.2 for a soft scatter, 1.0 for a full blast)
+    pub async fn update_with_intensity(&mut self, point: Point, intensity: f64) -> Result<()> {
+        // Borrowed, since the particle groups are a different field
+        let effect_types = &self.effect_types;
+        let effect_settings = EffectSettings {
+            explosion_intensity: intensity.max(0.0),
+            ..self.effect_settings
+        };
 
-        // Iterate over each particle group in parallel
+        // Iterate over each particle group in parallel, the frozen ones keep their state
+        let collision = self.collision;
         self.particle_system
             .par_iter_mut()
+            .zip(self.rngs.par_iter_mut())
+            .zip(self.frozen.par_iter())
             .enumerate()
-            .for_each(|(i, particles)| {
+            .filter(|(_, (_, &frozen))| !frozen)
+            .for_each(|(i, ((particles, rng), _))| {
                 let effect_type = effect_types[i];
                 for particle in particles.iter_mut() {
-                    particle.update_with_effect(&effect_type, point, interference_distance);
+                    particle.update_with_effect(&effect_type, point, &effect_settings, rng);
+                }
+
+                // Dissolved particles are gone for good, so the layer ends up empty
+                if effect_type == EffectType::Dissolve {
+                    particles.retain(|particle| particle.alpha >= MIN_VISIBLE_ALPHA);
+                }
+
+                if collision {
+                    Self::separate_particles(particles);
                 }
             });
 
-        // Update animation statuses
-        self.animation_statuses = self
-            .particle_system
+        // Update animation statuses of the layers that moved
+        for (index, particles) in self.particle_system.iter_mut().enumerate() {
+            if self.frozen[index] {
+                continue;
+            }
+            let animating = !particles.iter().all(|p| p.on_position);
+            self.effect_frames[index] = if animating {
+                self.effect_frames[index] + 1
+            } else {
+                0
+            };
+
+            // Reset the layers whose effect ran out of frames, e.g. debris stuck on the floor
+            if animating
+                && self.effect_max_frames[index].is_some_and(|max| self.effect_frames[index] >= max)
+            {
+                particles.iter_mut().for_each(Particle::reset);
+                self.effect_frames[index] = 0;
+                self.animation_statuses[index] = false;
+            } else {
+                self.animation_statuses[index] = animating;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Push apart the particles of a group that overlap, e.g. in a pile on the break floor
+    // The neighbours are looked up in a grid, so only nearby pairs are compared
+    // Particles at their origin are left alone, they are already laid out on the pixel grid
+    fn separate_particles(particles: &mut [Particle]) {
+        if particles.len() < 2 {
+            return;
+        }
+
+        let max_size = particles
             .iter()
-            .map(|particles| !particles.iter().all(|p| p.on_position))
+            .map(|particle| particle.size)
+            .max()
+            .unwrap_or(1);
+        let centers: Vec<(f64, f64)> = particles.iter().map(Particle::center).collect();
+        let points: Vec<Point> = centers
+            .iter()
+            .map(|&(x, y)| Point::new(x.round() as i32, y.round() as i32))
             .collect();
+        // Cells as large as the largest particle hold every overlapping neighbour
+        let grid = PointGrid::new(&points, max_size + 1);
+
+        // Gather the offsets first, so the result doesn't depend on the particle order
+        let mut offsets = vec![(0.0, 0.0); particles.len()];
+        for (i, particle) in particles.iter().enumerate() {
+            for (j, _) in grid.near(points[i]) {
+                let other = &particles[j];
+                if j <= i || (particle.at_origin() && other.at_origin()) {
+                    continue;
+                }
+
+                let dx = centers[j].0 - centers[i].0;
+                let dy = centers[j].1 - centers[i].1;
+                let distance = (dx * dx + dy * dy).sqrt();
+                let min_distance = (particle.size + other.size) as f64 / 2.0;
+                if distance >= min_distance {
+                    continue;
+                }
+
+                // Stacked particles have no direction between them, so separate them sideways
+                let (nx, ny) = if distance > f64::EPSILON {
+                    (dx / distance, dy / distance)
+                } else {
+                    (1.0, 0.0)
+                };
+                let push = (min_distance - distance) / 2.0;
+                offsets[i].0 -= nx * push;
+                offsets[i].1 -= ny * push;
+                offsets[j].0 += nx * push;
+                offsets[j].1 += ny * push;
+            }
+        }
+
+        for (particle, (dx, dy)) in particles.iter_mut().zip(offsets) {
+            particle.x += dx;
+            particle.y += dy;
+        }
+    }
 
+    // Push the particles of the layers at rest away from the given point, e.g. the mouse cursor
+    // Only the particles within the interference distance are moved
+    pub fn push_at(&mut self, point: Point) {
+        let effect_settings = self.effect_settings;
+        self.particle_system
+            .par_iter_mut()
+            .zip(self.rngs.par_iter_mut())
+            .zip(self.animation_statuses.par_iter())
+            .zip(self.frozen.par_iter())
+            .filter(|((_, &animating), &frozen)| !animating && !frozen)
+            .for_each(|(((particles, rng), _), _)| {
+                for particle in particles.iter_mut() {
+                    let dx = point.x as f64 - particle.x;
+                    let dy = point.y as f64 - particle.y;
+                    if dx * dx + dy * dy < effect_settings.interference_distance {
+                        particle.update_with_effect(
+                            &EffectType::Push,
+                            point,
+                            &effect_settings,
+                            rng,
+                        );
+                    }
+                }
+            });
+    }
+
+    // Snap all particles back to their origin and clear the animation statuses
+    pub fn reset(&mut self) {
+        self.particle_system
+            .par_iter_mut()
+            .for_each(|particles| particles.iter_mut().for_each(Particle::reset));
+        self.animation_statuses.iter_mut().for_each(|s| *s = false);
+        self.effect_frames.iter_mut().for_each(|frames| *frames = 0);
+    }
+
+    // Replace the whole output frame with the background
+    fn fill_background(&mut self) -> Result<()> {
+        match &self.background {
+            Background::Color(color) => {
+                self.output_frame.set_to(color, &core::no_array())?;
+            }
+            Background::Image(image) => image.copy_to(&mut self.output_frame)?,
+        }
         Ok(())
     }
 
     pub fn clean_output_frame(&mut self) -> Result<()> {
-        self.output_frame
-            .set_to(&core::Scalar::all(255.0), &core::no_array())?;
+        if self.trail_decay >= 1.0 {
+            return self.fill_background();
+        }
+
+        // Blend the previous frame towards the background, leaving fading trails behind moving particles
+        let color_frame;
+        let background = match &self.background {
+            Background::Color(color) => {
+                color_frame = Mat::new_size_with_default(
+                    self.output_frame.size()?,
+                    self.output_frame.typ(),
+                    *color,
+                )?;
+                &color_frame
+            }
+            Background::Image(image) => image,
+        };
+        let mut blended = Mat::default();
+        core::add_weighted(
+            &self.output_frame,
+            self.trail_decay,
+            background,
+            1.0 - self.trail_decay,
+            0.0,
+            &mut blended,
+            -1,
+        )?;
+        self.output_frame = blended;
+        Ok(())
+    }
+
+    // Draw a particle filling the rectangle with the given shape
+    fn draw_shape(
+        frame: &mut Mat,
+        rect: Rect,
+        color: Scalar,
+        shape: ParticleShape,
+        line_type: i32,
+    ) -> Result<()> {
+        match shape {
+            ParticleShape::Rect => {
+                imgproc::rectangle(frame, rect, color, -1, line_type, 0)?;
+            }
+            ParticleShape::Circle => {
+                // Centered in the square of the particle, so circles line up with the pixel grid
+                let center = Point::new(rect.x + rect.width / 2, rect.y + rect.height / 2);
+                let radius = (rect.width / 2).max(1);
+                imgproc::circle(frame, center, radius, color, -1, line_type, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Blend a particle shape onto the frame with the given opacity
+    fn blend_shape(
+        frame: &mut Mat,
+        rect: Rect,
+        color: Scalar,
+        alpha: f64,
+        shape: ParticleShape,
+        line_type: i32,
+    ) -> Result<()> {
+        // Clip the rectangle to the frame, since particles can sit on the edges
+        let x = rect.x.max(0);
+        let y = rect.y.max(0);
+        let width = (rect.x + rect.width).min(frame.cols()) - x;
+        let height = (rect.y + rect.height).min(frame.rows()) - y;
+        if width <= 0 || height <= 0 {
+            return Ok(());
+        }
+
+        let mut region = Mat::roi_mut(frame, Rect::new(x, y, width, height))?;
+        let background = region.try_clone()?;
+        let overlay = match shape {
+            ParticleShape::Rect => {
+                Mat::new_size_with_default(background.size()?, background.typ(), color)?
+            }
+            ParticleShape::Circle => {
+                // Draw the shape on a copy of the background, relative to the clipped region
+                let mut overlay = background.try_clone()?;
+                let local_rect = Rect::new(rect.x - x, rect.y - y, rect.width, rect.height);
+                Self::draw_shape(&mut overlay, local_rect, color, shape, line_type)?;
+                overlay
+            }
+        };
+        core::add_weighted(
+            &background,
+            1.0 - alpha,
+            &overlay,
+            alpha,
+            0.0,
+            &mut *region,
+            -1,
+        )?;
         Ok(())
     }
 
     pub fn draw(&mut self) -> Result<()> {
-        // Create a list of pixel to draw
-        let mut pixels = Vec::new();
-        let mut colors = Vec::new();
-
-        for particles in &self.particle_system {
-            for particle in particles {
-                pixels.push(Rect::new(
-                    particle.x as i32,
-                    particle.y as i32,
-                    particle.size,
-                    particle.size,
-                ));
-                colors.push(particle.color);
+        let spawn_frames = self.spawn_frames;
+        let leave_frames = self.leave_frames;
+        // Create a list of pixel to draw in parallel, skipping the particles that faded out
+        let pixels: Vec<(Rect, Scalar, f64)> = self
+            .particle_system
+            .par_iter()
+            .flat_map(|particles| {
+                particles
+                    .par_iter()
+                    .map(move |particle| {
+                        (particle, particle.visible_alpha(spawn_frames, leave_frames))
+                    })
+                    .filter(|(_, alpha)| *alpha >= MIN_VISIBLE_ALPHA)
+                    .map(|(particle, alpha)| {
+                        (
+                            Rect::new(
+                                particle.x as i32,
+                                particle.y as i32,
+                                particle.size,
+                                particle.size,
+                            ),
+                            particle.color,
+                            alpha,
+                        )
+                    })
+            })
+            .collect();
+
+        // Draw all pixel in a single loop, since they all share the output frame
+        let shape = self.particle_shape;
+        let line_type = if self.antialiasing {
+            imgproc::LINE_AA
+        } else {
+            imgproc::LINE_8
+        };
+        for (pixel, color, alpha) in pixels {
+            if alpha >= 1.0 {
+                Self::draw_shape(&mut self.output_frame, pixel, color, shape, line_type)?;
+            } else {
+                Self::blend_shape(
+                    &mut self.output_frame,
+                    pixel,
+                    color,
+                    alpha,
+                    shape,
+                    line_type,
+                )?;
             }
         }
 
-        // Draw all pixel in a single loop
-        for (pixel, color) in pixels.iter().zip(colors.iter()) {
-            imgproc::rectangle(
-                &mut self.output_frame,
-                *pixel,
-                *color,
-                -1,
-                imgproc::LINE_8,
-                0,
-            )?;
+        Ok(())
+    }
+
+    // Describe the visible particles as an SVG document of the window size
+    fn to_svg(&self) -> String {
+        // Colors are stored as BGR, SVG expects RGB
+        let rgb = |color: Scalar| {
+            format!(
+                "rgb({},{},{})",
+                color[2].round() as u8,
+                color[1].round() as u8,
+                color[0].round() as u8
+            )
+        };
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">\n",
+            self.window_size.width, self.window_size.height
+        );
+        // Image backgrounds are left out, only the particles are vector data
+        if let Background::Color(color) = &self.background {
+            let _ = writeln!(
+                svg,
+                "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+                rgb(*color)
+            );
         }
 
+        for particle in self.particle_system.iter().flatten() {
+            let alpha = particle.visible_alpha(self.spawn_frames, self.leave_frames);
+            if alpha < MIN_VISIBLE_ALPHA {
+                continue;
+            }
+            let fill = rgb(particle.color);
+            let opacity = if alpha < 1.0 {
+                format!(" fill-opacity=\"{:.2}\"", alpha)
+            } else {
+                String::new()
+            };
+            let size = particle.size as f64;
+            let _ = match self.particle_shape {
+                ParticleShape::Rect => writeln!(
+                    svg,
+                    "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{}\" height=\"{}\" fill=\"{}\"{}/>",
+                    particle.x, particle.y, particle.size, particle.size, fill, opacity
+                ),
+                ParticleShape::Circle => writeln!(
+                    svg,
+                    "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{:.1}\" fill=\"{}\"{}/>",
+                    particle.x + size / 2.0,
+                    particle.y + size / 2.0,
+                    size / 2.0,
+                    fill,
+                    opacity
+                ),
+            };
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    // Save the current particle layout as an SVG file, e.g. for a crisp print of the scene
+    pub fn export_svg(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_svg())?;
         Ok(())
     }
 
+    // Describe the state of all layers and their particles as JSON, for debugging
+    pub fn dump_state(&self) -> Value {
+        let layers: Vec<Value> = self
+            .particle_system
+            .iter()
+            .enumerate()
+            .map(|(index, particles)| {
+                json!({
+                    "effect_type": format!("{:?}", self.effect_types[index]),
+                    "animating": self.animation_statuses[index],
+                    "reacts_to_interference": self.reacts_to_interference[index],
+                    "particles": particles.iter().map(Particle::dump_state).collect::<Vec<Value>>(),
+                })
+            })
+            .collect();
+
+        json!({
+            "window_size": [self.window_size.width, self.window_size.height],
+            "pixel_size": self.pixel_size,
+            "pixel_spacing": self.pixel_spacing,
+            "layers": layers,
+        })
+    }
+
+    // Get the amount of particles of all layers
+    pub fn particle_count(&self) -> usize {
+        self.particle_system
+            .iter()
+            .map(|particles| particles.len())
+            .sum()
+    }
+
+    pub fn stats(&self) -> ParticleStats {
+        ParticleStats {
+            particles: self
+                .particle_system
+                .iter()
+                .map(|particles| particles.len())
+                .collect(),
+            on_position: self
+                .particle_system
+                .iter()
+                .map(|particles| particles.iter().filter(|p| p.on_position).count())
+                .collect(),
+        }
+    }
+
+    // Write the particle counts in the top left corner of the output frame
+    pub fn draw_stats(&mut self) -> Result<()> {
+        let stats = self.stats();
+        let mut lines = vec![format!(
+            "Particles: {} ({} at rest)",
+            stats.total_particles(),
+            stats.total_on_position()
+        )];
+        for (index, (particles, on_position)) in
+            stats.particles.iter().zip(&stats.on_position).enumerate()
+        {
+            lines.push(format!(
+                "Layer {}: {} ({} at rest)",
+                index, particles, on_position
+            ));
+        }
+
+        for (line_index, line) in lines.iter().enumerate() {
+            let origin = Point::new(20, 40 + line_index as i32 * 30);
+            // White outline, so the text is readable on any background
+            for (color, thickness) in [(Scalar::all(255.0), 4), (Scalar::all(0.0), 1)] {
+                imgproc::put_text(
+                    &mut self.output_frame,
+                    line,
+                    origin,
+                    imgproc::FONT_HERSHEY_SIMPLEX,
+                    0.8,
+                    color,
+                    thickness,
+                    imgproc::LINE_AA,
+                    false,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Error for a layer index beyond the layers created by init
+    fn missing_layer(index: usize, layers_amount: usize) -> anyhow::Error {
+        anyhow!(
+            "No particle layer {}, only {} layers were initialized",
+            index,
+            layers_amount
+        )
+    }
+
     pub fn get_animation_status(&self, index: usize) -> Result<bool> {
-        Ok(self.animation_statuses[index])
+        let layers_amount = self.animation_statuses.len();
+        self.animation_statuses
+            .get(index)
+            .copied()
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))
+    }
+
+    pub fn set_animation_status(&mut self, index: usize, status: bool) -> Result<()> {
+        let layers_amount = self.animation_statuses.len();
+        let animation_status = self
+            .animation_statuses
+            .get_mut(index)
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))?;
+        *animation_status = status;
+        Ok(())
+    }
+
+    // Check if any layer is animating an effect
+    pub fn is_animating(&self) -> bool {
+        self.animation_statuses.contains(&true)
+    }
+
+    pub fn reacts_to_interference(&self, index: usize) -> Result<bool> {
+        let layers_amount = self.reacts_to_interference.len();
+        self.reacts_to_interference
+            .get(index)
+            .copied()
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))
+    }
+
+    // Set if the layer plays an effect when its object interferes with another one
+    pub fn set_reacts_to_interference(&mut self, index: usize, reacts: bool) -> Result<()> {
+        let layers_amount = self.reacts_to_interference.len();
+        let layer_reacts = self
+            .reacts_to_interference
+            .get_mut(index)
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))?;
+        *layer_reacts = reacts;
+        Ok(())
     }
 
-    pub fn set_animation_status(&mut self, index: usize, status: bool) {
-        self.animation_statuses[index] = status;
+    pub fn is_frozen(&self, index: usize) -> Result<bool> {
+        let layers_amount = self.frozen.len();
+        self.frozen
+            .get(index)
+            .copied()
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))
     }
 
-    pub fn set_effect_type(&mut self, index: usize, effect_type: EffectType) {
-        self.effect_types[index] = effect_type;
+    // Pause or resume the physics of the layer, frozen particles are still drawn where they are
+    pub fn set_frozen(&mut self, index: usize, frozen: bool) -> Result<()> {
+        let layers_amount = self.frozen.len();
+        let layer_frozen = self
+            .frozen
+            .get_mut(index)
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))?;
+        *layer_frozen = frozen;
+        Ok(())
+    }
+
+    // Set after how many updates an effect on the layer is cut short and the particles
+    // snap back to their origin, None lets the effect run until they are back on their own
+    pub fn set_effect_max_frames(&mut self, index: usize, frames: Option<u32>) -> Result<()> {
+        let layers_amount = self.effect_max_frames.len();
+        let layer_max_frames = self
+            .effect_max_frames
+            .get_mut(index)
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))?;
+        *layer_max_frames = frames;
+        Ok(())
+    }
+
+    // Get the effects of all layers, one per layer
+    pub fn effect_types(&self) -> &[EffectType] {
+        &self.effect_types
+    }
+
+    // Set the effects of all layers at once, one per layer
+    pub fn set_all_effect_types(&mut self, effect_types: Vec<EffectType>) -> Result<()> {
+        ensure!(
+            effect_types.len() == self.effect_types.len(),
+            "Expected {} effect types, one per layer, got {}",
+            self.effect_types.len(),
+            effect_types.len()
+        );
+        self.effect_types = effect_types;
+        Ok(())
+    }
+
+    pub fn set_effect_type(&mut self, index: usize, effect_type: EffectType) -> Result<()> {
+        let layers_amount = self.effect_types.len();
+        let layer_effect_type = self
+            .effect_types
+            .get_mut(index)
+            .ok_or_else(|| Self::missing_layer(index, layers_amount))?;
+        *layer_effect_type = effect_type;
+        Ok(())
+    }
+
+    // Set the squared radius of the push effect (px²), used from the next update on
+    pub fn set_interference_distance(&mut self, interference_distance: f64) {
+        self.effect_settings.interference_distance = interference_distance.max(0.0);
+    }
+
+    // Get the squared radius of the push effect (px²)
+    pub fn interference_distance(&self) -> f64 {
+        self.effect_settings.interference_distance
+    }
+
+    // Push apart the overlapping particles of the animating layers, off by default since
+    // it adds a neighbour search per layer and update
+    pub fn set_collision(&mut self, collision: bool) {
+        self.collision = collision;
+    }
+
+    // Seed the random numbers of the effects and the size jitter, so the same input gives
+    // the same output, e.g. to record identical takes
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = Some(seed);
+        for (index, rng) in self.rngs.iter_mut().enumerate() {
+            *rng = Self::layer_rng(self.seed, index);
+        }
+    }
+
+    pub fn set_pixel_size(&mut self, pixel_size: i32) {
+        self.pixel_size = pixel_size.max(1);
+    }
+
+    pub fn set_swirl_clockwise(&mut self, clockwise: bool) {
+        self.effect_settings.swirl_clockwise = clockwise;
+    }
+
+    // Set how fast particles gather back to their origin (0.05 by default)
+    // Values near 0.01 give a slow gather, values near 0.3 a snappy one
+    pub fn set_reassembly_speed(&mut self, speed: f64) {
+        self.effect_settings.reassembly_speed = speed.clamp(0.001, 1.0);
+    }
+
+    pub fn set_snap_to_grid(&mut self, snap_to_grid: bool) {
+        self.snap_to_grid = snap_to_grid;
+    }
+
+    // Set how much of the previous frame is kept when cleaning the output frame,
+    // 1.0 disables the trails and lower values give longer trails
+    pub fn set_trail_decay(&mut self, factor: f64) {
+        self.trail_decay = factor.clamp(0.0, 1.0);
+    }
+
+    // Set how much the particle sizes vary around the pixel size (e.g. 0.3 for ±30%)
+    pub fn set_size_jitter(&mut self, fraction: f64) {
+        self.size_jitter = fraction.clamp(0.0, 1.0);
+    }
+
+    // Set how the particles are drawn
+    pub fn set_particle_shape(&mut self, shape: ParticleShape) {
+        self.particle_shape = shape;
+    }
+
+    // Smooth the particle edges, mostly visible on circles
+    // Anti-aliased drawing is slightly slower, which adds up with many particles
+    pub fn set_antialiasing(&mut self, antialiasing: bool) {
+        self.antialiasing = antialiasing;
+    }
+
+    // Set over how many frames new particles fade in, 0 makes them appear at once
+    pub fn set_spawn_frames(&mut self, frames: u32) {
+        self.spawn_frames = frames;
+    }
+
+    // Set over how many frames the particles that left their object fade out
+    // 0 drops them at once
+    pub fn set_leave_frames(&mut self, frames: u32) {
+        self.leave_frames = frames;
+    }
+
+    // Set the color the particles fade towards (BGR), None fades their opacity instead
+    // Set by the background, so call it afterwards to fade towards another color
+    pub fn set_fade_target(&mut self, target: Option<Scalar>) {
+        self.effect_settings.fade_target = target;
+    }
+
+    // Set what the particles are drawn on, images are resized to the window
+    // A plain color also becomes the color the particles fade towards, while particles
+    // fade their opacity over images
+    pub fn set_background(&mut self, background: Background) -> Result<()> {
+        self.background = match background {
+            Background::Color(color) => {
+                self.effect_settings.fade_target = Some(color);
+                Background::Color(color)
+            }
+            Background::Image(image) => {
+                ensure!(!image.empty(), "Background image is empty");
+
+                let mut color_image = Mat::default();
+                if image.channels() == 1 {
+                    imgproc::cvt_color(&image, &mut color_image, imgproc::COLOR_GRAY2BGR, 0)?;
+                } else if image.channels() == 4 {
+                    imgproc::cvt_color(&image, &mut color_image, imgproc::COLOR_BGRA2BGR, 0)?;
+                } else {
+                    color_image = image;
+                }
+
+                let mut resized = Mat::default();
+                imgproc::resize(
+                    &color_image,
+                    &mut resized,
+                    self.window_size,
+                    0.0,
+                    0.0,
+                    imgproc::INTER_LINEAR,
+                )?;
+                self.effect_settings.fade_target = None;
+                Background::Image(resized)
+            }
+        };
+
+        // Apply it right away, so the trails start from the new background
+        if !self.output_frame.empty() {
+            self.fill_background()?;
+        }
+        Ok(())
+    }
+
+    // Set the downward acceleration of the break effect
+    pub fn set_gravity(&mut self, gravity: f64) {
+        self.effect_settings.gravity = gravity;
+    }
+
+    // Set the distance of the break floor above the bottom edge of the window
+    pub fn set_floor_offset(&mut self, floor_offset: i32) {
+        self.effect_settings.floor_offset = floor_offset.clamp(0, self.window_size.height);
+    }
+
+    // Set the fraction of the speed kept when particles bounce off the floor (0.0 to stick)
+    pub fn set_floor_restitution(&mut self, floor_restitution: f64) {
+        self.effect_settings.floor_restitution = floor_restitution.clamp(0.0, 1.0);
+    }
+
+    // Let darker particles fall faster and lighter ones flutter in the break effect,
+    // instead of the whole object dropping as a uniform sheet
+    pub fn set_mass_variation(&mut self, enabled: bool) {
+        self.effect_settings.use_mass = enabled;
+    }
+
+    // Set the color fast explosion particles are tinted towards (BGR)
+    pub fn set_hot_color(&mut self, hot_color: Scalar) {
+        self.effect_settings.hot_color = hot_color;
+    }
+
+    // Set the force blowing the debris of the break and explosion effects each update,
+    // (0, 0) for still air
+    pub fn set_wind(&mut self, fx: f64, fy: f64) {
+        self.effect_settings.wind = (fx, fy);
+    }
+
+    // Set the color fireworks particles fade towards (BGR)
+    pub fn set_spark_color(&mut self, spark_color: Scalar) {
+        self.effect_settings.spark_color = spark_color;
+    }
+
+    // Set how the particles behave when they reach the window edges
+    pub fn set_boundary_mode(&mut self, boundary_mode: BoundaryMode) {
+        self.effect_settings.boundary_mode = boundary_mode;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil;
+
+    fn particle_at(x: i32, y: i32) -> Particle {
+        Particle::new(
+            Size::new(1000, 1000),
+            Point::new(x, y),
+            10,
+            Scalar::all(0.0),
+        )
+    }
+
+    // Seeded random numbers, so the tests are reproducible
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0)
+    }
+
+    fn speed(particle: &Particle) -> f64 {
+        (particle.vx * particle.vx + particle.vy * particle.vy).sqrt()
+    }
+
+    #[tokio::test]
+    async fn effect_resets_after_the_max_frames() -> Result<()> {
+        let frame = testutil::blank_frame(Size::new(100, 100), Scalar::all(255.0))?;
+        let mut particle_system = ParticleSystem::new(Size::new(100, 100), 10, 0, 10);
+        particle_system.init(&frame, 1)?;
+        particle_system.particle_system[0].push(particle_at(50, 20));
+        particle_system.set_effect_type(0, EffectType::Break)?;
+        particle_system.set_animation_status(0, true)?;
+        particle_system.set_effect_max_frames(0, Some(3))?;
+
+        for _ in 0..2 {
+            particle_system.update(Point::new(0, 0)).await?;
+        }
+        assert!(particle_system.get_animation_status(0)?);
+
+        particle_system.update(Point::new(0, 0)).await?;
+        assert!(!particle_system.get_animation_status(0)?);
+        let particle = &particle_system.particle_system[0][0];
+        assert_eq!((particle.x, particle.y), (50.0, 20.0));
+        Ok(())
+    }
+
+    #[test]
+    fn svg_has_an_element_per_visible_particle() {
+        let mut particle_system = ParticleSystem::new(Size::new(100, 50), 10, 0, 10);
+        let mut faded = particle_at(40, 20);
+        faded.alpha = 0.0;
+        let mut red = particle_at(10, 20);
+        red.color = Scalar::new(0.0, 0.0, 255.0, 0.0);
+        particle_system.particle_system.push(vec![red, faded]);
+
+        let svg = particle_system.to_svg();
+
+        assert!(svg.starts_with("<svg"), "{}", svg);
+        assert!(svg.contains(
+            "<rect x=\"10.0\" y=\"20.0\" width=\"10\" height=\"10\" fill=\"rgb(255,0,0)\"/>"
+        ));
+        assert_eq!(svg.matches("<rect").count(), 2, "{}", svg); // Background and red particle
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[tokio::test]
+    async fn particles_leaving_the_object_fade_out() -> Result<()> {
+        let frame = Arc::new(testutil::blank_frame(
+            Size::new(100, 100),
+            Scalar::all(0.0),
+        )?);
+        let mut particle_system = ParticleSystem::new(Size::new(100, 100), 10, 0, 10);
+        particle_system.init(&frame, 1)?;
+        particle_system.set_leave_frames(2);
+
+        let object = vec![Point::new(10, 10), Point::new(20, 10)];
+        particle_system
+            .add_object(Arc::clone(&frame), &object, 0)
+            .await?;
+        let shrunk = vec![Point::new(10, 10)];
+        particle_system
+            .add_object(Arc::clone(&frame), &shrunk, 0)
+            .await?;
+
+        let leaving = &particle_system.particle_system[0][1];
+        assert_eq!(particle_system.particle_system[0].len(), 2);
+        assert_eq!(leaving.origin, Point::new(20, 10));
+        assert!(leaving.visible_alpha(0, 2) < 1.0);
+
+        for _ in 0..2 {
+            particle_system
+                .add_object(Arc::clone(&frame), &shrunk, 0)
+                .await?;
+        }
+        assert_eq!(particle_system.particle_system[0].len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn seeded_systems_play_the_same_effect() -> Result<()> {
+        let frame = testutil::blank_frame(Size::new(1000, 1000), Scalar::all(255.0))?;
+        let mut positions = Vec::new();
+        for _ in 0..2 {
+            let mut particle_system = ParticleSystem::new(Size::new(1000, 1000), 10, 0, 10);
+            particle_system.init(&frame, 1)?;
+            particle_system.set_seed(7);
+            particle_system.particle_system[0].push(particle_at(500, 500));
+            particle_system.set_effect_type(0, EffectType::Break)?;
+            for _ in 0..5 {
+                particle_system.update(Point::new(0, 0)).await?;
+            }
+            let particle = &particle_system.particle_system[0][0];
+            positions.push((particle.x, particle.y));
+        }
+
+        assert_eq!(positions[0], positions[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn dissolved_particles_are_removed() -> Result<()> {
+        let frame = testutil::blank_frame(Size::new(100, 100), Scalar::all(255.0))?;
+        let mut particle_system = ParticleSystem::new(Size::new(100, 100), 10, 0, 10);
+        particle_system.init(&frame, 1)?;
+        particle_system.particle_system[0].push(particle_at(50, 50));
+        particle_system.set_effect_type(0, EffectType::Dissolve)?;
+        particle_system.set_animation_status(0, true)?;
+
+        particle_system.update(Point::new(0, 0)).await?;
+        assert!(particle_system.get_animation_status(0)?);
+        assert!(particle_system.particle_system[0][0].y < 50.5);
+
+        for _ in 0..200 {
+            particle_system.update(Point::new(0, 0)).await?;
+        }
+        assert!(particle_system.particle_system[0].is_empty());
+        assert!(!particle_system.get_animation_status(0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn push_moves_away_from_the_point() {
+        let mut rng = rng();
+        let mut particle = particle_at(500, 500);
+        let settings = EffectSettings::default();
+
+        particle.update_with_effect(&EffectType::Push, Point::new(505, 500), &settings, &mut rng);
+
+        assert!(particle.x < 500.0, "x = {}", particle.x);
+        assert!(!particle.on_position);
+    }
+
+    #[test]
+    fn push_ignores_points_out_of_reach() {
+        let mut rng = rng();
+        let mut particle = particle_at(500, 500);
+        let settings = EffectSettings::default();
+
+        particle.update_with_effect(&EffectType::Push, Point::new(900, 900), &settings, &mut rng);
+
+        assert_eq!((particle.x, particle.y), (500.0, 500.0));
+        assert!(particle.on_position);
+    }
+
+    #[test]
+    fn particles_return_to_their_origin() {
+        let mut rng = rng();
+        let mut particle = particle_at(100, 100);
+        particle.x = 300.0;
+        particle.y = 250.0;
+        let settings = EffectSettings::default();
+
+        for _ in 0..200 {
+            particle.update_with_effect(
+                &EffectType::Push,
+                Point::new(900, 900),
+                &settings,
+                &mut rng,
+            );
+        }
+
+        assert_eq!((particle.x, particle.y), (100.0, 100.0));
+        assert!(particle.on_position);
+    }
+
+    #[test]
+    fn explosion_moves_away_from_the_center_with_capped_speed() {
+        let mut rng = rng();
+        let mut particle = particle_at(500, 500);
+        let settings = EffectSettings::default();
+
+        for _ in 0..5 {
+            particle.update_with_effect(
+                &EffectType::Explosion,
+                Point::new(499, 500),
+                &settings,
+                &mut rng,
+            );
+            assert!(speed(&particle) <= 20.0, "speed = {}", speed(&particle));
+        }
+        assert!(particle.x > 500.0, "x = {}", particle.x);
+    }
+
+    #[test]
+    fn explosion_stops_at_the_window_edge() {
+        let mut rng = rng();
+        let mut particle = particle_at(990, 500);
+        let settings = EffectSettings::default();
+
+        for _ in 0..10 {
+            particle.update_with_effect(
+                &EffectType::Explosion,
+                Point::new(500, 500),
+                &settings,
+                &mut rng,
+            );
+        }
+
+        assert_eq!(particle.x, 1000.0);
+        assert_eq!(speed(&particle), 0.0);
+        assert!(particle.on_position);
+    }
+
+    #[test]
+    fn exploding_particles_keep_fading() {
+        let mut rng = rng();
+        let mut particle = particle_at(700, 500);
+        let settings = EffectSettings::default();
+
+        particle.update_with_effect(
+            &EffectType::Explosion,
+            Point::new(500, 500),
+            &settings,
+            &mut rng,
+        );
+        // Tinted towards the hot color while flying
+        assert!(particle.color[2] > particle.color[0]);
+
+        for _ in 0..39 {
+            particle.update_with_effect(
+                &EffectType::Explosion,
+                Point::new(500, 500),
+                &settings,
+                &mut rng,
+            );
+        }
+
+        // At rest the tint is gone, leaving the black faded towards the white background
+        let faded = 255.0 * (1.0 - 0.98f64.powi(40));
+        assert!(particle.on_position);
+        for channel in 0..3 {
+            assert!((particle.color[channel] - faded).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn break_falls_onto_the_floor() {
+        let mut rng = rng();
+        let mut particle = particle_at(500, 500);
+        let settings = EffectSettings::default();
+
+        for _ in 0..500 {
+            particle.update_with_effect(&EffectType::Break, Point::new(0, 0), &settings, &mut rng);
+        }
+
+        assert_eq!(particle.y, (1000 - DEFAULT_FLOOR_OFFSET) as f64);
+        assert!(particle.on_position);
+    }
+
+    #[test]
+    fn wind_blows_falling_particles_sideways() {
+        let mut rng = rng();
+        let mut particle = particle_at(500, 100);
+        let settings = EffectSettings {
+            wind: (5.0, 0.0),
+            ..EffectSettings::default()
+        };
+
+        particle.update_with_effect(&EffectType::Break, Point::new(0, 0), &settings, &mut rng);
+
+        assert!(particle.x > 500.0, "x = {}", particle.x);
+    }
+
+    #[test]
+    fn heavier_particles_fall_faster() {
+        let mut rng = rng();
+        let mut heavy = particle_at(300, 100);
+        heavy.mass = Particle::mass_of(Scalar::all(0.0));
+        let mut light = particle_at(700, 100);
+        light.mass = Particle::mass_of(Scalar::all(255.0));
+        let settings = EffectSettings {
+            use_mass: true,
+            ..EffectSettings::default()
+        };
+
+        for _ in 0..10 {
+            heavy.update_with_effect(&EffectType::Break, Point::new(0, 0), &settings, &mut rng);
+            light.update_with_effect(&EffectType::Break, Point::new(0, 0), &settings, &mut rng);
+        }
+
+        assert!(heavy.y > light.y, "heavy {} light {}", heavy.y, light.y);
+    }
+
+    #[test]
+    fn snapping_collapses_a_cluster_onto_the_grid() {
+        let pixel_size = 10;
+        let frame_size = Size::new(100, 100);
+        let mut cluster = Vec::new();
+        for x in 41..=44 {
+            for y in 48..=52 {
+                cluster.push(Point::new(x, y));
+            }
+        }
+        // Points on the edge would round to a cell outside the frame
+        cluster.push(Point::new(98, 99));
+        cluster.push(Point::new(99, 97));
+
+        let snapped = ParticleSystem::snap_points_to_grid(&cluster, pixel_size, frame_size);
+
+        assert_eq!(snapped, vec![Point::new(40, 50), Point::new(90, 90)]);
+        let unique: HashSet<(i32, i32)> = snapped.iter().map(|point| (point.x, point.y)).collect();
+        assert_eq!(unique.len(), snapped.len());
+        for point in &snapped {
+            assert_eq!(point.x % pixel_size, 0);
+            assert_eq!(point.y % pixel_size, 0);
+            assert!(point.x >= 0 && point.x < frame_size.width);
+            assert!(point.y >= 0 && point.y < frame_size.height);
+        }
+    }
+
+    #[test]
+    fn stacked_particles_are_pushed_apart() {
+        let mut particles = vec![particle_at(100, 100), particle_at(120, 100)];
+        for particle in particles.iter_mut() {
+            particle.x = 500.0;
+            particle.y = 900.0;
+        }
+
+        ParticleSystem::separate_particles(&mut particles);
+
+        let distance = (particles[1].x - particles[0].x).hypot(particles[1].y - particles[0].y);
+        assert!((distance - 10.0).abs() < 1e-9, "distance = {}", distance);
+    }
+
+    #[test]
+    fn particles_at_their_origin_are_not_separated() {
+        let mut particles = vec![particle_at(100, 100), particle_at(105, 100)];
+
+        ParticleSystem::separate_particles(&mut particles);
+
+        assert_eq!((particles[0].x, particles[1].x), (100.0, 105.0));
+    }
+
+    #[test]
+    fn fading_moves_the_color_towards_the_target() {
+        let mut particle = particle_at(500, 500);
+        particle.fade_color(0.5, Some(Scalar::all(255.0)));
+        assert_eq!(particle.color[0], 127.5);
+        assert_eq!(particle.alpha, 1.0);
+
+        particle.fade_color(0.5, None);
+        assert_eq!(particle.alpha, 0.5);
+    }
+
+    #[test]
+    fn clamp_keeps_particles_inside_the_window() {
+        let mut particle = particle_at(500, 500);
+        particle.x = -10.0;
+        particle.y = 1010.0;
+
+        assert!(particle.check_world_boundaries(BoundaryMode::Clamp));
+        assert_eq!((particle.x, particle.y), (0.0, 1000.0));
+    }
+
+    #[test]
+    fn bounce_reverses_the_velocity_with_energy_loss() {
+        let mut particle = particle_at(500, 500);
+        particle.x = -10.0;
+        particle.vx = -10.0;
+        particle.vy = 5.0;
+
+        assert!(particle.check_world_boundaries(BoundaryMode::Bounce));
+        assert_eq!(particle.x, 0.0);
+        assert_eq!(particle.vx, 10.0 * BOUNCE_RESTITUTION);
+        assert_eq!(particle.vy, 5.0);
+    }
+
+    #[test]
+    fn wrap_moves_particles_to_the_opposite_edge() {
+        let mut particle = particle_at(500, 500);
+        particle.x = 1010.0;
+        particle.y = -10.0;
+
+        assert!(particle.check_world_boundaries(BoundaryMode::Wrap));
+        assert_eq!((particle.x, particle.y), (10.0, 990.0));
+    }
+
+    #[test]
+    fn particles_inside_the_window_are_left_alone() {
+        let mut particle = particle_at(500, 500);
+
+        assert!(!particle.check_world_boundaries(BoundaryMode::Bounce));
+        assert_eq!((particle.x, particle.y), (500.0, 500.0));
     }
 }
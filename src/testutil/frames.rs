@@ -0,0 +1,19 @@
+use anyhow::Result;
+use opencv::{
+    core::{self, Rect, Scalar, Size},
+    imgproc,
+    prelude::*,
+};
+
+// Create a BGR frame filled with a single color
+pub fn blank_frame(size: Size, color: Scalar) -> Result<Mat> {
+    let frame = Mat::new_size_with_default(size, core::CV_8UC3, color)?;
+    Ok(frame)
+}
+
+// Create a white BGR frame with a filled black rectangle, like a dark object on a light wall
+pub fn frame_with_rect(size: Size, rect: Rect) -> Result<Mat> {
+    let mut frame = blank_frame(size, Scalar::all(255.0))?;
+    imgproc::rectangle(&mut frame, rect, Scalar::all(0.0), -1, imgproc::LINE_8, 0)?;
+    Ok(frame)
+}
@@ -0,0 +1,4 @@
+pub mod frames;
+
+pub use frames::blank_frame;
+pub use frames::frame_with_rect;
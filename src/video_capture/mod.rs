@@ -1,3 +1,9 @@
 pub mod video_capture;
+pub mod video_error;
+pub mod video_writer;
 
+pub use video_capture::Rotation;
+pub use video_capture::SourceType;
 pub use video_capture::VideoSource;
+pub use video_error::VideoError;
+pub use video_writer::VideoWriter;
@@ -1,57 +1,348 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::{bail, Result};
+use log::warn;
 use opencv::{
-    core::{self, flip},
+    core::{self, flip, Rect, Size},
     imgproc,
     prelude::*,
     videoio::{self, VideoCapture},
 };
+use tokio::{task, time};
+
+use super::video_error::{Result, VideoError};
+
+// Amount of failed reads in a row before reconnecting to a stream
+const STREAM_RECONNECT_AFTER: u32 = 30;
+// Wait between reconnection attempts, doubled after every failed attempt
+const STREAM_RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const STREAM_MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+// Enum to represent where the frames of a video source come from
+pub enum SourceType {
+    #[default]
+    None, // Nothing opened yet
+    File,   // Video file with a known length, can be looped
+    Webcam, // Local camera
+    Stream, // Network camera, reconnected when it drops
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+// Enum to represent the clockwise rotation of the captured frames
+pub enum Rotation {
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    // Parse the rotation from its degrees, only right angles are supported
+    pub fn from_degrees(degrees: u32) -> Option<Self> {
+        match degrees {
+            90 => Some(Rotation::Deg90),
+            180 => Some(Rotation::Deg180),
+            270 => Some(Rotation::Deg270),
+            _ => None,
+        }
+    }
+
+    // OpenCV rotate code of the rotation
+    fn code(self) -> i32 {
+        match self {
+            Rotation::Deg90 => core::ROTATE_90_CLOCKWISE,
+            Rotation::Deg180 => core::ROTATE_180,
+            Rotation::Deg270 => core::ROTATE_90_COUNTERCLOCKWISE,
+        }
+    }
+
+    // Size of a frame after the rotation, width and height swap for quarter turns
+    pub fn rotate_size(self, (width, height): (i32, i32)) -> (i32, i32) {
+        match self {
+            Rotation::Deg180 => (width, height),
+            Rotation::Deg90 | Rotation::Deg270 => (height, width),
+        }
+    }
+}
 
 pub struct VideoSource {
     capture: VideoCapture,
-    pub frame: Arc<Mutex<Mat>>,
+    frame: Arc<Mat>,
     resolution: (i32, i32),
-    source_type: String,
+    source_type: SourceType,
     constrast: f64,
     brightness: f64,
+    looping: bool,
+    flip_mode: Option<i32>,
+    rotation: Option<Rotation>,
+    roi: Option<Rect>,
+    crossfade_frames: u32,
+    crossfade_from: Option<Arc<Mat>>,
+    crossfade_remaining: u32,
+    frame_duration: Option<Duration>,
+    pending_time: Duration,
+    stream_url: Option<String>,
+    failed_reads: u32,
+    reconnect_backoff: Duration,
+    next_reconnect: Option<Instant>,
+    webcam_index: Option<i32>,
+    capture_properties: Option<(i32, i32, f64)>,
+    read_timeout: Option<Duration>,
+    stalled: bool,
 }
 
 impl VideoSource {
     pub fn new(resolution: (i32, i32)) -> Result<Self> {
         Ok(Self {
             capture: VideoCapture::default()?,
-            frame: Arc::new(Mutex::new(Mat::default())),
+            frame: Arc::new(Mat::default()),
             resolution,
-            source_type: String::from(""),
+            source_type: SourceType::None,
             constrast: 1.0,
             brightness: 0.0,
+            looping: false,
+            flip_mode: None,
+            rotation: None,
+            roi: None,
+            crossfade_frames: 0,
+            crossfade_from: None,
+            crossfade_remaining: 0,
+            frame_duration: None,
+            pending_time: Duration::ZERO,
+            stream_url: None,
+            failed_reads: 0,
+            reconnect_backoff: STREAM_RECONNECT_BACKOFF,
+            next_reconnect: None,
+            webcam_index: None,
+            capture_properties: None,
+            read_timeout: None,
+            stalled: false,
         })
     }
 
     pub fn set_source_file(&mut self, file_path: &String) -> Result<()> {
         if !std::path::Path::new(file_path).exists() {
-            bail!("File does not exist: {}", file_path);
+            return Err(VideoError::FileNotFound(file_path.clone()));
         }
         self.capture.open_file(file_path, videoio::CAP_ANY)?;
         if !self.capture.is_opened()? {
-            bail!("Unable to open video file: {}", file_path);
+            return Err(VideoError::OpenFailed(format!("video file {}", file_path)));
         }
 
-        self.source_type = "file".to_string();
+        self.source_type = SourceType::File;
+        self.flip_mode = None;
+
+        // Dissolve from the last frame of the previous file into the new one
+        if self.crossfade_frames > 0 && !self.frame.empty() {
+            self.crossfade_from = Some(Arc::clone(&self.frame));
+            self.crossfade_remaining = self.crossfade_frames;
+        }
+
+        // Pace the playback at the native frame rate of the file
+        let fps = self.fps()?;
+        self.frame_duration = if fps > 0.0 {
+            Some(Duration::from_secs_f64(1.0 / fps))
+        } else {
+            None
+        };
+        self.pending_time = Duration::ZERO;
         Ok(())
     }
 
     pub fn set_source_webcam(&mut self, webcam_index: i32) -> Result<()> {
         self.capture.open(webcam_index, videoio::CAP_ANY)?;
         if !self.capture.is_opened()? {
-            bail!("Unable to open the webcam!");
+            return Err(VideoError::OpenFailed(format!("webcam {}", webcam_index)));
         }
 
-        self.source_type = "webcam".to_string();
+        self.source_type = SourceType::Webcam;
+        self.webcam_index = Some(webcam_index);
+        self.capture_properties = None;
+        self.flip_mode = Some(1); // Mirror the webcam by default
+        self.frame_duration = None;
+        self.stalled = false;
         Ok(())
     }
 
+    // Open the webcam, retrying while it is not ready yet (e.g. still enumerating after boot)
+    // Returns the error of the last attempt once all attempts failed
+    pub fn set_source_webcam_retry(
+        &mut self,
+        webcam_index: i32,
+        attempts: u32,
+        delay: Duration,
+    ) -> Result<()> {
+        let attempts = attempts.max(1);
+        for attempt in 1..=attempts {
+            match self.set_source_webcam(webcam_index) {
+                Err(error @ VideoError::OpenFailed(_)) if attempt < attempts => {
+                    warn!("{} (attempt {}/{}), retrying", error, attempt, attempts);
+                    std::thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    // Open a network stream (e.g. an RTSP camera), paced and not flipped like a webcam
+    // If the stream can't be opened yet, the source still retries it on every update_frame
+    pub fn set_source_stream(&mut self, url: &str) -> Result<()> {
+        self.source_type = SourceType::Stream;
+        self.stream_url = Some(url.to_string());
+        self.flip_mode = None;
+        self.frame_duration = None;
+        self.failed_reads = 0;
+        self.reconnect_backoff = STREAM_RECONNECT_BACKOFF;
+        self.next_reconnect = None;
+        self.stalled = false;
+
+        self.capture.open_file(url, videoio::CAP_FFMPEG)?;
+        if !self.capture.is_opened()? {
+            return Err(VideoError::OpenFailed(format!("stream {}", url)));
+        }
+        Ok(())
+    }
+
+    // Reopen the stream after repeated failed reads, waiting longer after each failed attempt
+    fn reconnect_stream(&mut self) {
+        self.failed_reads += 1;
+        if self.failed_reads < STREAM_RECONNECT_AFTER {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .next_reconnect
+            .map_or(false, |next_reconnect| now < next_reconnect)
+        {
+            return;
+        }
+        let url = match &self.stream_url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+
+        warn!("Reconnecting to the stream: {}", url);
+        let reopened = self
+            .capture
+            .open_file(&url, videoio::CAP_FFMPEG)
+            .unwrap_or(false)
+            && self.capture.is_opened().unwrap_or(false);
+        if reopened {
+            self.failed_reads = 0;
+            self.reconnect_backoff = STREAM_RECONNECT_BACKOFF;
+            self.next_reconnect = None;
+        } else {
+            self.next_reconnect = Some(now + self.reconnect_backoff);
+            self.reconnect_backoff = (self.reconnect_backoff * 2).min(STREAM_MAX_RECONNECT_BACKOFF);
+        }
+    }
+
+    // Give up on a read of a webcam or stream after the timeout, e.g. when a flaky USB camera
+    // hangs. The source is then marked as stalled and reopened, None waits forever
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    // Name of the live source in the logs and errors
+    fn live_source_name(&self) -> String {
+        match (self.source_type, self.webcam_index, &self.stream_url) {
+            (SourceType::Webcam, Some(index), _) => format!("webcam {}", index),
+            (SourceType::Stream, _, Some(url)) => format!("stream {}", url),
+            _ => String::from("the video source"),
+        }
+    }
+
+    // Read the next frame, on a blocking task if a read timeout is set for a live source
+    // Returns None if the read timed out, the hung read then keeps the capture
+    async fn read_frame(&mut self) -> Result<Option<Mat>> {
+        let timeout = match self.read_timeout {
+            Some(timeout) if self.is_live() => timeout,
+            _ => {
+                let mut frame = Mat::default();
+                self.capture.read(&mut frame)?;
+                return Ok(Some(frame));
+            }
+        };
+
+        let mut capture = std::mem::replace(&mut self.capture, VideoCapture::default()?);
+        let read = task::spawn_blocking(move || {
+            let mut frame = Mat::default();
+            let result = capture.read(&mut frame).map(|_| frame);
+            (capture, result)
+        });
+        match time::timeout(timeout, read).await {
+            Ok(joined) => {
+                let (capture, result) = joined?;
+                self.capture = capture;
+                Ok(Some(result?))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    // Reopen a stalled webcam or stream with a new capture, waiting longer after each
+    // failed attempt. Clears the stalled flag once it is open again
+    fn reopen_stalled(&mut self) {
+        let now = Instant::now();
+        if self
+            .next_reconnect
+            .map_or(false, |next_reconnect| now < next_reconnect)
+        {
+            return;
+        }
+
+        let name = self.live_source_name();
+        warn!("Reopening the stalled {}", name);
+        let opened = match (self.source_type, self.webcam_index, &self.stream_url) {
+            (SourceType::Webcam, Some(index), _) => self.capture.open(index, videoio::CAP_ANY),
+            (SourceType::Stream, _, Some(url)) => self.capture.open_file(url, videoio::CAP_FFMPEG),
+            _ => Ok(false),
+        };
+        let reopened = opened.unwrap_or(false) && self.capture.is_opened().unwrap_or(false);
+        if reopened {
+            // The new capture starts with the camera defaults, ask for the same mode again
+            if let Some((width, height, fps)) = self.capture_properties {
+                if let Err(error) = self.set_capture_properties(width, height, fps) {
+                    warn!(
+                        "Unable to set the capture properties of {}: {}",
+                        name, error
+                    );
+                }
+            }
+            self.stalled = false;
+            self.reconnect_backoff = STREAM_RECONNECT_BACKOFF;
+            self.next_reconnect = None;
+        } else {
+            self.next_reconnect = Some(now + self.reconnect_backoff);
+            self.reconnect_backoff = (self.reconnect_backoff * 2).min(STREAM_MAX_RECONNECT_BACKOFF);
+        }
+    }
+
+    // Ask the opened camera for a native capture resolution and frame rate, instead of
+    // upscaling a small stream. Cameras snap to their supported modes, so the actual
+    // width, height and frame rate are read back and returned
+    pub fn set_capture_properties(
+        &mut self,
+        width: i32,
+        height: i32,
+        fps: f64,
+    ) -> Result<(i32, i32, f64)> {
+        self.capture_properties = Some((width, height, fps));
+        self.capture
+            .set(videoio::CAP_PROP_FRAME_WIDTH, width as f64)?;
+        self.capture
+            .set(videoio::CAP_PROP_FRAME_HEIGHT, height as f64)?;
+        self.capture.set(videoio::CAP_PROP_FPS, fps)?;
+
+        let actual_width = self.capture.get(videoio::CAP_PROP_FRAME_WIDTH)? as i32;
+        let actual_height = self.capture.get(videoio::CAP_PROP_FRAME_HEIGHT)? as i32;
+        let actual_fps = self.capture.get(videoio::CAP_PROP_FPS)?;
+        Ok((actual_width, actual_height, actual_fps))
+    }
+
     pub fn set_contrast(&mut self, contrast: f64) {
         self.constrast = contrast;
     }
@@ -60,12 +351,207 @@ impl VideoSource {
         self.brightness = brightness;
     }
 
-    pub fn update_frame(&mut self) -> Result<bool> {
-        let mut frame = Mat::default();
-        self.capture.read(&mut frame)?;
+    pub fn source_type(&self) -> SourceType {
+        self.source_type
+    }
+
+    // Check if the source delivers frames as they happen (webcams and streams) instead of
+    // playing a finite file
+    pub fn is_live(&self) -> bool {
+        matches!(self.source_type, SourceType::Webcam | SourceType::Stream)
+    }
+
+    // Get the amount of frames of a file source, None for live sources or unknown lengths
+    pub fn frame_count(&self) -> Option<i64> {
+        if self.source_type != SourceType::File {
+            return None;
+        }
+        let frame_count = self.capture.get(videoio::CAP_PROP_FRAME_COUNT).ok()? as i64;
+        (frame_count > 0).then_some(frame_count)
+    }
+
+    // Get a shared reference to the latest frame, without copying the pixels
+    pub fn current_frame(&self) -> Arc<Mat> {
+        Arc::clone(&self.frame)
+    }
+
+    // Get the native frame rate of the source
+    pub fn fps(&self) -> Result<f64> {
+        Ok(self.capture.get(videoio::CAP_PROP_FPS)?)
+    }
+
+    // Check if the next frame is due after the elapsed time, always true for live sources
+    pub fn should_advance(&mut self, elapsed: Duration) -> bool {
+        let frame_duration = match self.frame_duration {
+            Some(frame_duration) => frame_duration,
+            None => return true,
+        };
+
+        // Don't accumulate more than a frame of delay when the loop runs slower than the video
+        self.pending_time = (self.pending_time + elapsed).min(frame_duration * 2);
+        if self.pending_time >= frame_duration {
+            self.pending_time -= frame_duration;
+            return true;
+        }
+        false
+    }
+
+    // Restart file sources from the beginning when they end (ignored for live sources)
+    pub fn set_loop(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    // Set the flip axis of the frames (0 vertical, 1 horizontal, -1 both), None disables flipping
+    // Selecting a source resets it to its default, so call this afterwards
+    pub fn set_flip(&mut self, mode: Option<i32>) {
+        self.flip_mode = mode;
+    }
+
+    // Rotate the frames after capture, e.g. for a portrait display, None keeps them upright
+    // The frames then have the rotated resolution, with width and height swapped for 90 and 270
+    pub fn set_rotation(&mut self, rotation: Option<Rotation>) {
+        self.rotation = rotation;
+    }
+
+    // Resolution of the shown frames, after the rotation
+    pub fn output_resolution(&self) -> (i32, i32) {
+        match self.rotation {
+            Some(rotation) => rotation.rotate_size(self.resolution),
+            None => self.resolution,
+        }
+    }
+
+    // Set over how many frames switching to another file dissolves from the previous one
+    // 0 cuts to the new file at once
+    pub fn set_crossfade_frames(&mut self, frames: u32) {
+        self.crossfade_frames = frames;
+        if frames == 0 {
+            self.crossfade_from = None;
+            self.crossfade_remaining = 0;
+        }
+    }
+
+    // Blend the outgoing frame over the new one, fading it out over the crossfade frames
+    fn apply_crossfade(&mut self, frame: Mat) -> Result<Mat> {
+        let from = match &self.crossfade_from {
+            Some(from) if self.crossfade_remaining > 0 => Arc::clone(from),
+            _ => return Ok(frame),
+        };
+
+        let weight = self.crossfade_remaining as f64 / (self.crossfade_frames + 1) as f64;
+        self.crossfade_remaining -= 1;
+        if self.crossfade_remaining == 0 {
+            self.crossfade_from = None;
+        }
+
+        // Cut if the frames can't be blended, e.g. after the resolution changed
+        if from.size()? != frame.size()? || from.typ() != frame.typ() {
+            return Ok(frame);
+        }
+        let mut blended = Mat::default();
+        core::add_weighted(&*from, weight, &frame, 1.0 - weight, 0.0, &mut blended, -1)?;
+        Ok(blended)
+    }
+
+    // Only keep the region of the captured frames inside the rectangle, before resizing
+    // The region is checked against the capture size when it is already known
+    pub fn set_roi(&mut self, roi: Rect) -> Result<()> {
+        let frame_size = Size::new(
+            self.capture.get(videoio::CAP_PROP_FRAME_WIDTH)? as i32,
+            self.capture.get(videoio::CAP_PROP_FRAME_HEIGHT)? as i32,
+        );
+        if frame_size.width > 0 && frame_size.height > 0 {
+            Self::check_roi(roi, frame_size)?;
+        } else if roi.width <= 0 || roi.height <= 0 || roi.x < 0 || roi.y < 0 {
+            return Err(VideoError::InvalidRoi { roi, frame_size });
+        }
+        self.roi = Some(roi);
+        Ok(())
+    }
+
+    // Use the full captured frames again
+    pub fn clear_roi(&mut self) {
+        self.roi = None;
+    }
+
+    fn check_roi(roi: Rect, frame_size: Size) -> Result<()> {
+        let fits = roi.x >= 0
+            && roi.y >= 0
+            && roi.width > 0
+            && roi.height > 0
+            && roi.x + roi.width <= frame_size.width
+            && roi.y + roi.height <= frame_size.height;
+        if !fits {
+            return Err(VideoError::InvalidRoi { roi, frame_size });
+        }
+        Ok(())
+    }
+
+    // Show a white frame without any objects, until a source is opened
+    pub fn set_blank_frame(&mut self) -> Result<()> {
+        let (width, height) = self.output_resolution();
+        self.frame = Arc::new(Mat::new_rows_cols_with_default(
+            height,
+            width,
+            core::CV_8UC3,
+            core::Scalar::all(255.0),
+        )?);
+        Ok(())
+    }
+
+    // Close the capture, freeing the webcam or file
+    pub fn release(&mut self) -> Result<()> {
+        self.capture.release()?;
+        Ok(())
+    }
+
+    pub async fn update_frame(&mut self) -> Result<bool> {
+        // Skip the frames of a stalled source until it could be reopened
+        if self.stalled {
+            self.reopen_stalled();
+            if self.stalled {
+                return Err(VideoError::Stalled(self.live_source_name()));
+            }
+        }
+
+        let mut frame = match self.read_frame().await? {
+            Some(frame) => frame,
+            None => {
+                warn!("Reading from {} timed out", self.live_source_name());
+                self.stalled = true;
+                self.reconnect_backoff = STREAM_RECONNECT_BACKOFF;
+                self.next_reconnect = None;
+                self.reopen_stalled();
+                return Err(VideoError::Stalled(self.live_source_name()));
+            }
+        };
+        if frame.empty() && self.looping && self.source_type == SourceType::File {
+            // Seek back to the first frame instead of reopening the file
+            self.capture.set(videoio::CAP_PROP_POS_FRAMES, 0.0)?;
+            self.capture.read(&mut frame)?;
+        }
+        if frame.empty() && self.source_type == SourceType::Stream {
+            // Streams never end, keep the last frame while the connection is down
+            self.reconnect_stream();
+            return Ok(true);
+        }
+        if frame.empty() && self.source_type == SourceType::Webcam {
+            // Webcams never end, an empty frame means the camera stopped delivering
+            return Err(VideoError::EmptyFrame(String::from("the webcam")));
+        }
         if frame.empty() {
             return Ok(false);
         }
+        self.failed_reads = 0;
+
+        // Crop the frame to the region of interest
+        let frame = match self.roi {
+            Some(roi) => {
+                Self::check_roi(roi, frame.size()?)?;
+                Mat::roi(&frame, roi)?.try_clone()?
+            }
+            None => frame,
+        };
 
         // Resize the frame to the desired resolution
         let mut resized_frame = Mat::default();
@@ -78,22 +564,35 @@ impl VideoSource {
             imgproc::INTER_LINEAR,
         )?;
 
-        if self.source_type == "webcam" {
+        // Adjust the contrast and brightness, skipped with the neutral defaults
+        let adjusted_frame = if self.constrast != 1.0 || self.brightness != 0.0 {
             let mut bright_frame = Mat::default();
             resized_frame.convert_to(&mut bright_frame, -1, self.constrast, self.brightness)?;
+            bright_frame
+        } else {
+            resized_frame
+        };
 
-            // Flip the frame vertically
+        let final_frame = if let Some(flip_mode) = self.flip_mode {
+            // Flip the frame around the configured axis
             let mut flipped_frame = Mat::default();
-            flip(&bright_frame, &mut flipped_frame, 1)?;
+            flip(&adjusted_frame, &mut flipped_frame, flip_mode)?;
+            flipped_frame
+        } else {
+            adjusted_frame
+        };
 
-            // Update the shared frame with the brightened frame
-            let mut shared_frame = self.frame.lock().unwrap();
-            *shared_frame = flipped_frame;
+        let final_frame = if let Some(rotation) = self.rotation {
+            // Rotate the frame, after flipping so the mirror axis stays the camera's
+            let mut rotated_frame = Mat::default();
+            core::rotate(&final_frame, &mut rotated_frame, rotation.code())?;
+            rotated_frame
         } else {
-            // Update the shared frame with the resized frame
-            let mut shared_frame = self.frame.lock().unwrap();
-            *shared_frame = resized_frame;
-        }
+            final_frame
+        };
+
+        // Update the shared frame, dissolving from the previous file after a switch
+        self.frame = Arc::new(self.apply_crossfade(final_frame)?);
 
         Ok(true)
     }
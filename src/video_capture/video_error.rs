@@ -0,0 +1,23 @@
+use opencv::core::{Rect, Size};
+use thiserror::Error;
+
+// Errors of the video sources, so callers can tell a missing file from a camera failure
+#[derive(Debug, Error)]
+pub enum VideoError {
+    #[error("File does not exist: {0}")]
+    FileNotFound(String),
+    #[error("Unable to open {0}")]
+    OpenFailed(String),
+    #[error("No frame could be read from {0}")]
+    EmptyFrame(String),
+    #[error("Reading from {0} timed out, the source is stalled")]
+    Stalled(String),
+    #[error("The capture read task failed: {0}")]
+    ReadTask(#[from] tokio::task::JoinError),
+    #[error("Region of interest {roi:?} does not fit in the {frame_size:?} frame")]
+    InvalidRoi { roi: Rect, frame_size: Size },
+    #[error("Unable to decode the video: {0}")]
+    DecodeError(#[from] opencv::Error),
+}
+
+pub type Result<T, E = VideoError> = std::result::Result<T, E>;
@@ -0,0 +1,46 @@
+use anyhow::{bail, Result};
+use opencv::{core::Size, prelude::*, videoio};
+
+pub struct VideoWriter {
+    writer: videoio::VideoWriter,
+    path: String,
+    frame_size: Size,
+}
+
+impl VideoWriter {
+    pub fn open(path: &str, fps: f64, frame_size: Size) -> Result<Self> {
+        let fourcc = videoio::VideoWriter::fourcc('m', 'p', '4', 'v')?;
+        let writer = videoio::VideoWriter::new(path, fourcc, fps, frame_size, true)?;
+        if !writer.is_opened()? {
+            bail!(
+                "Unable to open the video writer: {} (is the mp4v codec available?)",
+                path
+            );
+        }
+
+        Ok(Self {
+            writer,
+            path: path.to_string(),
+            frame_size,
+        })
+    }
+
+    pub fn write(&mut self, frame: &Mat) -> Result<()> {
+        // OpenCV silently drops frames of the wrong size, leaving an empty file
+        if frame.size()? != self.frame_size {
+            bail!(
+                "Frame size {:?} does not match the recording size {:?}: {}",
+                frame.size()?,
+                self.frame_size,
+                self.path
+            );
+        }
+        self.writer.write(frame)?;
+        Ok(())
+    }
+
+    pub fn release(&mut self) -> Result<()> {
+        self.writer.release()?;
+        Ok(())
+    }
+}